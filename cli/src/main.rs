@@ -1,7 +1,9 @@
 use std::{fs::File, io::{Read, Write}, path::Path, str::FromStr};
 
-use clap::{Args, Parser, Subcommand};
-use core::{chunk::Chunk, chunk_type::ChunkType, png::Png, Result};  
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use core::{chunk::Chunk, chunk_type::ChunkType, optimize::{self, OptimizeOptions}, png::{Png, StripPolicy}, Result};  
+use core::{analysis, apng, ecc, fragment, keyed_type, registry, envelope::{self, Compression, Decryption, Encryption, Message}, recipient::{Identity, Recipient}, shamir::{self, Share}, signature::{self, SigningKey, VerifyingKey}};
+use core::stego::{lsb::{self, ChannelMask, LsbOptions}, palette, text::{self, TextOptions}, timing, zlib_slack};
 
 /// png tools
 #[derive(Parser)]
@@ -16,14 +18,91 @@ enum Commands {
     /// Encode a chunk into a PNG file
     Encode(EncodeArgs),
     /// Decode a chunk from a PNG file
-    Decode(DecodeOrRemoveArgs),
+    Decode(DecodeArgs),
     /// Remove a chunk from a PNG file
-    Remove(DecodeOrRemoveArgs),
+    Remove(RemoveArgs),
     /// Print the binary of the PNG file
     Print{
         /// Path to the image file
         img_path: String,
     },
+    /// Losslessly shrink a PNG file
+    Optimize(OptimizeArgs),
+    /// Remove metadata chunks from a PNG file
+    Strip(StripArgs),
+    /// Look for signs of hidden data in a PNG file
+    Analyze {
+        /// Path to the image file
+        img_path: String,
+    },
+    /// Generate a key pair for recipient encryption
+    Keygen {
+        /// Where to write the secret key, the public key goes to <output>.pub
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Generate a signing key pair instead
+        #[arg(long)]
+        signing: bool,
+    },
+    /// Shard a message across several PNG files
+    Split(SplitArgs),
+    /// Rebuild a message sharded with split
+    Join(JoinArgs),
+    /// Sign chunks of a PNG file
+    Sign(SignArgs),
+    /// Check the signature of a PNG file
+    Verify {
+        /// Path to the image file
+        img_path: String,
+        /// Only accept signatures from this public key or public key file
+        #[arg(long)]
+        key: Option<String>,
+    },
+    /// Check chunk order, multiplicity and names against the PNG specification
+    Validate {
+        /// Path to the image file
+        img_path: String,
+    },
+    /// List the messages in a PNG file without decrypting them
+    List {
+        /// Path to the image file
+        img_path: String,
+    },
+    /// Extract or build the frames of an animated PNG
+    Frames {
+        #[command(subcommand)]
+        command: FramesCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum FramesCommand {
+    /// Save every frame of an animated PNG as it is displayed
+    Extract {
+        /// Path to the image file
+        img_path: String,
+        /// Directory for the frames, defaults to the image's directory
+        #[arg(short, long)]
+        output_dir: Option<String>,
+    },
+    /// Build an animated PNG from still images
+    Build(FramesBuildArgs),
+}
+
+#[derive(Args)]
+struct FramesBuildArgs {
+    /// Paths to the frames, in order
+    #[arg(required = true)]
+    img_paths: Vec<String>,
+    /// Where to write the animation
+    #[arg(short, long)]
+    output: String,
+    /// Delay of each frame in milliseconds, one for all frames or one per frame
+    #[arg(long, default_values_t = [100])]
+    delay: Vec<u16>,
+    /// Number of times to play the animation, 0 for forever
+    #[arg(long, default_value_t = 0)]
+    loops: u32,
 }
 
 #[derive(Args)]
@@ -31,22 +110,393 @@ struct CommandArgs {
     list: Vec<String>,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Method {
+    /// Store the message in a chunk of its own
+    Chunk,
+    /// Hide the message in the least significant bits of the pixels
+    Lsb,
+    /// Hide the message in the order of the palette of an indexed image
+    Palette,
+    /// Hide the message after the end of the compressed image data
+    ZlibSlack,
+    /// Disguise the message as base64 text in a standard text chunk
+    Text,
+    /// Hide the message in equivalent fractions of the frame delays of an animated PNG
+    Timing,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CompressionArg {
+    Deflate,
+    Zstd,
+}
+
+impl From<CompressionArg> for Compression {
+    fn from(arg: CompressionArg) -> Self {
+        match arg {
+            CompressionArg::Deflate => Compression::Deflate,
+            CompressionArg::Zstd => Compression::Zstd,
+        }
+    }
+}
+
+#[derive(Args)]
+struct LsbArgs {
+    /// Low bits of each sample that carry the message (lsb method)
+    #[arg(long, default_value_t = 1)]
+    bits: u8,
+    /// Channels that carry the message, e.g. rgb or rgba (lsb method)
+    #[arg(long, default_value = "rgb")]
+    channels: String,
+    /// Passphrase that scatters the message over the pixels (lsb method)
+    #[arg(long)]
+    key: Option<String>,
+}
+
+impl LsbArgs {
+    fn options(&self) -> Result<LsbOptions> {
+        Ok(LsbOptions {
+            bits_per_channel: self.bits,
+            channels: ChannelMask::from_str(&self.channels)?,
+            key: self.key.clone(),
+        })
+    }
+}
+
+#[derive(Args)]
+struct TextArgs {
+    /// Keyword of the text chunk (text method)
+    #[arg(long, default_value = text::DEFAULT_KEYWORD)]
+    keyword: String,
+    /// Use an iTXt chunk instead of tEXt (text method)
+    #[arg(long)]
+    itxt: bool,
+}
+
+impl TextArgs {
+    fn options(&self) -> TextOptions {
+        TextOptions {
+            keyword: self.keyword.clone(),
+            international: self.itxt,
+        }
+    }
+}
+
+#[derive(Args)]
+struct KeyedTypeArgs {
+    /// Derive the chunk type from this shared secret instead of naming it (chunk method)
+    #[arg(long, conflicts_with = "type_identity")]
+    type_secret: Option<String>,
+    /// Derive the chunk type from this secret key file and --type-peer (chunk method)
+    #[arg(long, requires = "type_peer")]
+    type_identity: Option<String>,
+    /// Public key or public key file of the other side (chunk method)
+    #[arg(long, requires = "type_identity")]
+    type_peer: Option<String>,
+}
+
+impl KeyedTypeArgs {
+    fn chunk_type(&self) -> Result<Option<ChunkType>> {
+        Ok(match (&self.type_secret, &self.type_identity, &self.type_peer) {
+            (Some(secret), _, _) => Some(keyed_type::from_passphrase(secret)),
+            (None, Some(identity), Some(peer)) => {
+                Some(read_identity(identity)?.shared_chunk_type(&read_recipient(peer)?))
+            }
+            _ => None,
+        })
+    }
+}
+
+#[derive(Args)]
+struct SealArgs {
+    /// Encrypt the message with a passphrase (read from PCM_PASSPHRASE or prompted)
+    #[arg(long, conflicts_with = "recipient")]
+    encrypt: bool,
+    /// Encrypt the message to a public key or public key file, can be repeated
+    #[arg(long)]
+    recipient: Vec<String>,
+    /// Compress the message before encrypting and embedding it
+    #[arg(long, value_enum)]
+    compress: Option<CompressionArg>,
+    /// Add error correction able to repair this percentage of damaged bytes
+    #[arg(long, value_name = "PERCENT")]
+    ecc: Option<f64>,
+    /// Author or label stored in the clear next to the message
+    #[arg(long)]
+    label: Option<String>,
+    /// Let the message expire after this long, e.g. 90s, 30m, 12h or 7d
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    expires_in: Option<u64>,
+    /// Record when the message was written
+    #[arg(long)]
+    timestamp: bool,
+}
+
+impl SealArgs {
+    fn seal(&self, message: &Message) -> Result<Vec<u8>> {
+        let encryption = if !self.recipient.is_empty() {
+            let recipients = self.recipient.iter().map(|r| read_recipient(r)).collect::<Result<Vec<Recipient>>>()?;
+            Some(Encryption::Recipients(recipients))
+        } else {
+            self.encrypt.then(|| Encryption::Passphrase(read_passphrase()))
+        };
+        let mut message = message.clone();
+        // 设置了过期时间时总是记录创建时间
+        if self.timestamp || self.expires_in.is_some() {
+            let now = envelope::now();
+            message.metadata.created = Some(now);
+            message.metadata.expires = self.expires_in.map(|secs| now + secs);
+        }
+        message.metadata.label = self.label.clone();
+        let payload = message.seal(self.compress.map(Compression::from).unwrap_or_default(), encryption.as_ref())?;
+        match self.ecc {
+            Some(percent) => ecc::encode(&payload, ecc::parity_for(percent / 100.0)?),
+            None => Ok(payload),
+        }
+    }
+}
+
+#[derive(Args)]
+struct OpenArgs {
+    /// Decrypt the message with a passphrase (read from PCM_PASSPHRASE or prompted)
+    #[arg(long, conflicts_with = "identity")]
+    decrypt: bool,
+    /// Decrypt the message with the secret key in this file
+    #[arg(long)]
+    identity: Option<String>,
+    /// Write the decoded bytes to this file instead of printing them
+    #[arg(short, long)]
+    output: Option<String>,
+    /// Show expired messages instead of refusing them
+    #[arg(long)]
+    ignore_expiry: bool,
+}
+
+impl OpenArgs {
+    fn decryption(&self) -> Result<Option<Decryption>> {
+        Ok(match &self.identity {
+            Some(path) => Some(Decryption::Identity(read_identity(path)?)),
+            None => self.decrypt.then(|| Decryption::Passphrase(read_passphrase())),
+        })
+    }
+
+    fn open(&self, payload: &[u8], decryption: Option<&Decryption>) -> Result<Message> {
+        let message = match ecc::detect(payload)? {
+            Some(corrected) => {
+                println!("Error correction repaired {} damaged bytes", corrected.errors);
+                Message::open(&corrected.data, decryption)?
+            }
+            None => Message::open(payload, decryption)?,
+        };
+        if !message.metadata.is_empty() {
+            println!("{}", message.metadata);
+        }
+        if message.metadata.is_expired() {
+            if !self.ignore_expiry {
+                return Err("The message has expired, use --ignore-expiry to read it anyway".into());
+            }
+            eprintln!("Warning: the message has expired");
+        }
+        Ok(message)
+    }
+
+    fn show(&self, message: Result<Message>) {
+        match (message, &self.output) {
+            (Ok(message), Some(output)) => {
+                std::fs::write(output, &message.data).unwrap();
+                println!("Saved {} bytes ({}) to {}", message.data.len(), message.content_type, output);
+            }
+            (Ok(message), None) if message.is_text() => {
+                println!("Decoded chunk to String: {}", String::from_utf8_lossy(&message.data));
+            }
+            (Ok(message), None) => eprintln!(
+                "Decoded {} bytes of {} ({}), use --output to save them",
+                message.data.len(),
+                message.content_type,
+                message.filename.as_deref().unwrap_or("no filename"),
+            ),
+            (Err(e), _) => eprintln!("Decoded failed: {}", e),
+        }
+    }
+}
+
 #[derive(Args)]
 struct EncodeArgs {
     /// Path to the image file
     img_path: String,
-    /// Type of the chunk to add
-    chunk_type: String,
+    /// Type of the chunk to add (only for the chunk method)
+    chunk_type: Option<String>,
     /// Data to add to the chunk 
-    chunk_data: String,
+    chunk_data: Option<String>,
+    /// Where the message is hidden
+    #[arg(long, value_enum, default_value_t = Method::Chunk)]
+    method: Method,
+    #[command(flatten)]
+    keyed_type: KeyedTypeArgs,
+    #[command(flatten)]
+    lsb: LsbArgs,
+    #[command(flatten)]
+    text: TextArgs,
+    #[command(flatten)]
+    seal: SealArgs,
+    /// Embed the contents of this file instead of a text message
+    #[arg(long, conflicts_with = "chunk_data")]
+    file: Option<String>,
+    /// Split the message over several chunks of at most this many bytes (chunk method)
+    #[arg(long)]
+    fragment_size: Option<usize>,
+    /// Keep unknown unsafe-to-copy chunks when the image data changes
+    #[arg(long)]
+    keep_unsafe: bool,
+}
+
+impl EncodeArgs {
+    // 只有 chunk 方式需要 chunk type，其它方式以及派生 chunk type 时唯一的位置参数就是数据
+    fn chunk_type_and_data(&self) -> Result<(Option<String>, Option<&str>)> {
+        let only_data = self.chunk_data.as_deref().or(self.chunk_type.as_deref());
+        Ok(match (self.method, self.keyed_type.chunk_type()?) {
+            (Method::Chunk, Some(derived)) => (Some(derived.to_string()), only_data),
+            (Method::Chunk, None) => (self.chunk_type.clone(), self.chunk_data.as_deref()),
+            _ => (None, only_data),
+        })
+    }
+}
+
+#[derive(Args)]
+struct DecodeArgs {
+    /// Path to the image file
+    img_path: String,
+    /// Type of the chunk to decode (only for the chunk method)
+    chunk_type: Option<String>,
+    /// Where the message is hidden
+    #[arg(long, value_enum, default_value_t = Method::Chunk)]
+    method: Method,
+    #[command(flatten)]
+    keyed_type: KeyedTypeArgs,
+    #[command(flatten)]
+    lsb: LsbArgs,
+    #[command(flatten)]
+    text: TextArgs,
+    #[command(flatten)]
+    open: OpenArgs,
+}
+
+#[derive(Args)]
+struct SplitArgs {
+    /// Type of the chunk to add to every image
+    chunk_type: String,
+    /// Images to spread the message over, one shard each
+    #[arg(required = true, num_args = 2..)]
+    img_paths: Vec<String>,
+    /// Text message to split
+    #[arg(short, long, required_unless_present = "file", conflicts_with = "file")]
+    message: Option<String>,
+    /// Split the contents of this file instead of a text message
+    #[arg(long)]
+    file: Option<String>,
+    /// Use secret sharing so that any this many images recover the message
+    #[arg(long)]
+    threshold: Option<u8>,
+    #[command(flatten)]
+    seal: SealArgs,
+}
+
+#[derive(Args)]
+struct JoinArgs {
+    /// Type of the chunk holding the shards
+    chunk_type: String,
+    /// Images holding the shards, in any order
+    #[arg(required = true)]
+    img_paths: Vec<String>,
+    #[command(flatten)]
+    open: OpenArgs,
 }
+
+
 #[derive(Args)]
-struct DecodeOrRemoveArgs {
+struct RemoveArgs {
     /// Path to the image file
     img_path: String,
     /// Type of the chunk to operate on
     chunk_type: String,
 }
+#[derive(Args)]
+struct SignArgs {
+    /// Path to the image file
+    img_path: String,
+    /// File holding the signing key
+    #[arg(long)]
+    key: String,
+    /// Chunk types to sign, defaults to IHDR, IDAT and all message chunks
+    #[arg(long)]
+    chunk: Vec<String>,
+    /// Where to write the result, defaults to overwriting the input
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+#[derive(Args)]
+struct StripArgs {
+    /// Path to the image file
+    img_path: String,
+    /// Where to write the result, defaults to overwriting the input
+    #[arg(short, long)]
+    output: Option<String>,
+    /// Only remove chunks that may identify the author: tIME, eXIf, tEXt, zTXt and iTXt
+    #[arg(long, conflicts_with = "all_ancillary")]
+    privacy: bool,
+    /// Remove every ancillary chunk, including colour and transparency information
+    #[arg(long)]
+    all_ancillary: bool,
+    /// Chunk types to keep, can be repeated
+    #[arg(long)]
+    keep: Vec<String>,
+    /// Keep private ancillary chunks such as encoded messages
+    #[arg(long)]
+    keep_messages: bool,
+}
+
+impl StripArgs {
+    fn policy(&self) -> Result<StripPolicy> {
+        let mut policy = if self.privacy {
+            StripPolicy::privacy()
+        } else if self.all_ancillary {
+            StripPolicy::all_ancillary()
+        } else {
+            StripPolicy::default()
+        };
+        for chunk_type in &self.keep {
+            policy.keep.push(ChunkType::from_str(chunk_type)?);
+        }
+        policy.keep_messages = self.keep_messages;
+        Ok(policy)
+    }
+}
+
+#[derive(Args)]
+struct OptimizeArgs {
+    /// Path to the image file
+    img_path: String,
+    /// Where to write the result, defaults to overwriting the input
+    #[arg(short, long)]
+    output: Option<String>,
+    /// Keep private ancillary chunks such as encoded messages
+    #[arg(long)]
+    keep_messages: bool,
+    /// Additional chunk types to keep
+    #[arg(long)]
+    keep: Vec<String>,
+    /// Compression levels to try (0-9)
+    #[arg(long, default_values_t = [6, 9], value_parser = clap::value_parser!(u32).range(0..=9))]
+    level: Vec<u32>,
+    /// Do not change the colour type or bit depth
+    #[arg(long)]
+    no_reduce: bool,
+    /// Keep unknown unsafe-to-copy chunks although the image data is rewritten
+    #[arg(long)]
+    keep_unsafe: bool,
+}
 
 fn main() {
     let cli = Cli::parse();
@@ -55,14 +505,53 @@ fn main() {
             println!("encoding for {}",args.img_path);
 
             let mut png = open_png(&args.img_path).unwrap();
-            let chunk_type = ChunkType::from_str(&args.chunk_type).unwrap();
-            let chunk = Chunk::new(chunk_type, args.chunk_data.as_bytes().to_vec());
-            png.append_chunk(chunk);
+            let (chunk_type, chunk_data) = args.chunk_type_and_data().unwrap();
+            let message = read_message(args.file.as_deref(), chunk_data).unwrap();
+            let payload = args.seal.seal(&message).unwrap();
+            // 像素、调色板和 IDAT 都是关键块，依赖它们的未知块由 embed 移除并返回
+            let dropped = match args.method {
+                Method::Chunk => {
+                    let chunk_type = ChunkType::from_str(chunk_type.as_deref().expect("No chunk type was provided.")).unwrap();
+                    // 超过单个块的长度上限时自动分片
+                    let fragment_size = args.fragment_size.or(
+                        (payload.len() > fragment::MAX_CHUNK_DATA).then_some(fragment::DEFAULT_FRAGMENT_SIZE),
+                    );
+                    match fragment_size {
+                        Some(size) => {
+                            let count = fragment::embed(&mut png, &chunk_type, &payload, size).unwrap();
+                            println!("Split the message over {} chunks", count);
+                        }
+                        None => png.append_chunk(Chunk::new(chunk_type, payload)),
+                    }
+                    Vec::new()
+                }
+                Method::Lsb => lsb::embed(&mut png, &payload, &args.lsb.options().unwrap()).unwrap(),
+                Method::Palette => palette::embed(&mut png, &payload).unwrap(),
+                Method::ZlibSlack => zlib_slack::embed(&mut png, &payload).unwrap(),
+                Method::Text => {
+                    text::embed(&mut png, &payload, &args.text.options()).unwrap();
+                    Vec::new()
+                }
+                Method::Timing => {
+                    timing::embed(&mut png, &payload).unwrap();
+                    Vec::new()
+                }
+            };
+            for chunk in dropped {
+                if args.keep_unsafe {
+                    eprintln!("Warning: kept unsafe-to-copy chunk {} although the image data changed", chunk.chunk_type());
+                    png.append_chunk(chunk);
+                } else {
+                    println!("Dropped unsafe-to-copy chunk {} because the image data changed", chunk.chunk_type());
+                }
+            }
 
+            let method = args.method.to_possible_value().unwrap();
+            let file_name = chunk_type.as_deref().unwrap_or(method.get_name());
             let path = Path::new(&args.img_path);
             let path_buf = path.parent().map(|p| p.to_path_buf());
             if let Some( mut path_buf) = path_buf {
-                path_buf.push(format!("{}.png",args.chunk_type ));
+                path_buf.push(format!("{}.png", file_name));
                 let mut new_file = File::create(&path_buf).unwrap();
                 new_file.write_all(&png.as_bytes()).unwrap();
                 println!("Saved to {:?}", path_buf.display());
@@ -73,21 +562,63 @@ fn main() {
         }
 
         Some(Commands::Decode(args)) => {
-            let png = open_png(&args.img_path).unwrap();
-            let chunk = png.chunk_by_type(&args.chunk_type).unwrap();
+            let png = open_damaged_png(&args.img_path).unwrap();
+            let decryption = args.open.decryption().unwrap();
+            let payload = match args.method {
+                Method::Chunk => {
+                    let derived = args.keyed_type.chunk_type().unwrap().map(|t| t.to_string());
+                    let chunk_type = derived.or(args.chunk_type.clone()).expect("No chunk type was provided.");
+                    fragment::extract(&png, &chunk_type)
+                }
+                Method::Lsb => lsb::extract(&png, &args.lsb.options().unwrap()),
+                Method::Palette => palette::extract(&png),
+                Method::ZlibSlack => zlib_slack::extract(&png),
+                Method::Text => text::extract(&png, &args.text.options()),
+                Method::Timing => timing::extract(&png),
+            };
+            let message = payload.and_then(|bytes| args.open.open(&bytes, decryption.as_ref()));
+            args.open.show(message);
+        },
 
-            if let Ok(chunk_data_str) = chunk.data_as_string() {
-                println!("Decoded chunk to String: {}", chunk_data_str);
-            } else {
-                eprintln!("Decoded failed.");
+        Some(Commands::Split(args)) => {
+            let mut pngs: Vec<Png> = args.img_paths.iter().map(|p| open_png(p).unwrap()).collect();
+            let message = read_message(args.file.as_deref(), args.message.as_deref()).unwrap();
+            let payload = args.seal.seal(&message).unwrap();
+            let chunk_type = ChunkType::from_str(&args.chunk_type).unwrap();
+            match args.threshold {
+                Some(threshold) => shamir::embed_across(&mut pngs, &chunk_type, &payload, threshold).unwrap(),
+                None => fragment::embed_across(&mut pngs, &chunk_type, &payload).unwrap(),
+            }
+
+            for (index, (png, img_path)) in pngs.iter().zip(&args.img_paths).enumerate() {
+                let mut path_buf = Path::new(img_path).parent().map(|p| p.to_path_buf()).unwrap_or_default();
+                path_buf.push(format!("{}-{}.png", args.chunk_type, index));
+                let mut new_file = File::create(&path_buf).unwrap();
+                new_file.write_all(&png.as_bytes()).unwrap();
+                println!("Saved shard {} of {} to {:?}", index + 1, pngs.len(), path_buf.display());
             }
+        },
 
+        Some(Commands::Join(args)) => {
+            let pngs: Vec<Png> = args.img_paths.iter().map(|p| open_damaged_png(p).unwrap()).collect();
+            let decryption = args.open.decryption().unwrap();
+            let is_shared = pngs
+                .iter()
+                .filter_map(|png| png.chunk_by_type(&args.chunk_type))
+                .any(|chunk| matches!(Share::detect(chunk.data()), Ok(Some(_))));
+            let payload = if is_shared {
+                shamir::extract_across(&pngs, &args.chunk_type)
+            } else {
+                fragment::extract_across(&pngs, &args.chunk_type)
+            };
+            let message = payload.and_then(|bytes| args.open.open(&bytes, decryption.as_ref()));
+            args.open.show(message);
         },
 
         Some(Commands::Remove(args)) => {
 
             let mut png = open_png(&args.img_path).unwrap();
-            if let Ok(_) = png.remove_first_chunk(&args.chunk_type) {
+            if png.remove_first_chunk(&args.chunk_type).is_ok() {
                 let mut new_file = File::create(&args.img_path).unwrap();
                 new_file.write_all(&png.as_bytes()).unwrap();
                 println!("Removed chunk: {} and overwrote {}", args.chunk_type , args.img_path);
@@ -102,6 +633,196 @@ fn main() {
             println!("{}", png);
         },
 
+        Some(Commands::Optimize(args)) => {
+            let png = open_png(&args.img_path).unwrap();
+            let keep = args.keep.iter().map(|t| ChunkType::from_str(t).unwrap()).collect();
+            let options = OptimizeOptions {
+                levels: args.level.clone(),
+                reduce: !args.no_reduce,
+                keep_messages: args.keep_messages,
+                keep,
+                keep_unsafe: args.keep_unsafe,
+                ..OptimizeOptions::default()
+            };
+            let optimized = optimize::optimize(&png, &options).unwrap();
+
+            let output = args.output.as_ref().unwrap_or(&args.img_path);
+            let mut new_file = File::create(output).unwrap();
+            new_file.write_all(&optimized.as_bytes()).unwrap();
+            println!(
+                "Optimized {} bytes -> {} bytes, saved to {}",
+                png.as_bytes().len(),
+                optimized.as_bytes().len(),
+                output
+            );
+        },
+
+        Some(Commands::Strip(args)) => {
+            let mut png = open_png(&args.img_path).unwrap();
+            let removed = png.strip(&args.policy().unwrap());
+            let output = args.output.as_ref().unwrap_or(&args.img_path);
+            let mut new_file = File::create(output).unwrap();
+            new_file.write_all(&png.as_bytes()).unwrap();
+            let names: Vec<String> = removed.iter().map(|c| c.chunk_type().to_string()).collect();
+            if names.is_empty() {
+                println!("Nothing to strip, saved to {}", output);
+            } else {
+                println!("Removed {}, saved to {}", names.join(", "), output);
+            }
+        },
+
+        Some(Commands::Analyze { img_path }) => {
+            let png = open_png(img_path).unwrap();
+            let findings = analysis::analyze(&png).unwrap();
+            if findings.is_empty() {
+                println!("Nothing suspicious found in {}", img_path);
+            }
+            for finding in findings {
+                println!("{}", finding);
+            }
+        },
+
+        Some(Commands::Keygen { output, signing }) => {
+            let (secret, public) = if *signing {
+                let key = SigningKey::generate();
+                (key.to_string(), key.verifying_key().to_string())
+            } else {
+                let identity = Identity::generate();
+                (identity.to_string(), identity.recipient().to_string())
+            };
+            match output {
+                Some(output) => match write_secret_key(output, &secret) {
+                    Ok(()) => {
+                        std::fs::write(format!("{}.pub", output), format!("{}\n", public)).unwrap();
+                        println!("Saved secret key to {} and public key to {}.pub", output, output);
+                        println!("Public key: {}", public);
+                    }
+                    Err(e) => eprintln!("Failed to save the secret key: {}", e),
+                },
+                None => {
+                    println!("{}", secret);
+                    println!("Public key: {}", public);
+                }
+            }
+        },
+
+        Some(Commands::Sign(args)) => {
+            let mut png = open_png(&args.img_path).unwrap();
+            let key = SigningKey::from_str(&std::fs::read_to_string(&args.key).unwrap()).unwrap();
+            let types = if args.chunk.is_empty() {
+                signature::default_selection(&png)
+            } else {
+                args.chunk.iter().map(|t| ChunkType::from_str(t).unwrap()).collect()
+            };
+            signature::sign(&mut png, &key, &types).unwrap();
+
+            let output = args.output.as_ref().unwrap_or(&args.img_path);
+            let mut new_file = File::create(output).unwrap();
+            new_file.write_all(&png.as_bytes()).unwrap();
+            let names: Vec<String> = types.iter().map(|t| t.to_string()).collect();
+            println!("Signed {} chunks, saved to {}", names.join(", "), output);
+        },
+
+        Some(Commands::Verify { img_path, key }) => {
+            let png = open_png(img_path).unwrap();
+            let trusted = key.as_deref().map(|k| read_verifying_key(k).unwrap());
+            match signature::verify(&png, trusted.as_ref()) {
+                Ok(verification) => {
+                    println!("Signed by {}", verification.signer);
+                    for covered in &verification.covered {
+                        println!("  {}", covered);
+                    }
+                    for (chunk_type, occurrence) in &verification.uncovered {
+                        println!("  {} #{}: not covered", chunk_type, occurrence);
+                    }
+                    if verification.is_intact() && trusted.is_some() {
+                        println!("Signature is valid");
+                    } else if verification.is_intact() {
+                        // 没有 --key 时签名者可以是任何人，包括伪造者自己
+                        eprintln!("Warning: signer not authenticated (no --key given)");
+                        println!("Signature matches the key embedded in the file");
+                    } else {
+                        eprintln!("Signed chunks were altered after signing");
+                    }
+                }
+                Err(e) => eprintln!("Verification failed: {}", e),
+            }
+        },
+
+        Some(Commands::Validate { img_path }) => {
+            let png = open_png(img_path).unwrap();
+            let issues = registry::validate(&png);
+            if issues.is_empty() {
+                println!("{} follows the specification", img_path);
+            }
+            for issue in issues {
+                println!("{}", issue);
+            }
+        },
+
+        Some(Commands::List { img_path }) => {
+            let png = open_damaged_png(img_path).unwrap();
+            let messages = envelope::list(&png);
+            if messages.is_empty() {
+                println!("No messages found in {}", img_path);
+            }
+            for (chunk_type, envelope) in messages {
+                let mut details = vec![envelope.content_type.clone(), format!("{} bytes", envelope.body.len())];
+                details.extend(envelope.filename.clone());
+                if envelope.is_encrypted() {
+                    details.push("encrypted".to_string());
+                }
+                if envelope.is_compressed() {
+                    details.push("compressed".to_string());
+                }
+                if envelope.is_signed() {
+                    details.push("signed".to_string());
+                }
+                if let Some(share) = envelope.share {
+                    details.push(format!("share {} of {}, {} needed", share.index, share.count, share.threshold));
+                }
+                if !envelope.metadata.is_empty() {
+                    details.push(envelope.metadata.to_string());
+                }
+                println!("{}: {}", chunk_type, details.join(", "));
+            }
+        },
+
+        Some(Commands::Frames { command: FramesCommand::Extract { img_path, output_dir } }) => {
+            let png = open_png(img_path).unwrap();
+            let animation = png.animation().unwrap().expect("Not an animated PNG");
+            let frames = apng::render(&png).unwrap();
+            let path = Path::new(img_path);
+            let dir = match output_dir {
+                Some(dir) => Path::new(dir).to_path_buf(),
+                None => path.parent().map(|p| p.to_path_buf()).unwrap_or_default(),
+            };
+            let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("frame");
+            for (index, (raster, frame)) in frames.iter().zip(&animation.frames).enumerate() {
+                let path_buf = dir.join(format!("{}-{}.png", stem, index));
+                let mut new_file = File::create(&path_buf).unwrap();
+                new_file.write_all(&raster.to_png().unwrap().as_bytes()).unwrap();
+                println!("Saved frame {} ({}s) to {}", index, frame.control.delay(), path_buf.display());
+            }
+        },
+
+        Some(Commands::Frames { command: FramesCommand::Build(args) }) => {
+            let images: Vec<Png> = args.img_paths.iter().map(|p| open_png(p).unwrap()).collect();
+            let delays: Option<Vec<(u16, u16)>> = match args.delay.as_slice() {
+                [delay] => Some(vec![(*delay, 1000); images.len()]),
+                delays if delays.len() == images.len() => Some(delays.iter().map(|&d| (d, 1000)).collect()),
+                _ => None,
+            };
+            if let Some(delays) = delays {
+                let png = apng::assemble(&images, &delays, args.loops).unwrap();
+                let mut new_file = File::create(&args.output).unwrap();
+                new_file.write_all(&png.as_bytes()).unwrap();
+                println!("Saved {} frames to {}", images.len(), args.output);
+            } else {
+                eprintln!("Give one delay for all frames or one per frame, got {} delays for {} frames", args.delay.len(), images.len());
+            }
+        },
+
         None => {
             eprintln!("No command was provided. Use --help for more information.");
         }
@@ -111,11 +832,80 @@ fn main() {
 }
 
 
+// 优先读取环境变量，方便在脚本中使用
+fn read_passphrase() -> String {
+    std::env::var("PCM_PASSPHRASE")
+        .unwrap_or_else(|_| rpassword::prompt_password("Passphrase: ").unwrap())
+}
+
+// 有文件时读取文件内容，否则把参数当作文本消息
+fn read_message(file: Option<&str>, text: Option<&str>) -> Result<Message> {
+    match file {
+        Some(file) => {
+            let name = Path::new(file).file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            Ok(Message::file(name, std::fs::read(file)?))
+        }
+        None => Ok(Message::text(text.ok_or("No data to encode was provided.")?)),
+    }
+}
+
+// 参数既可以是公钥本身，也可以是保存公钥的文件
+fn read_recipient(arg: &str) -> Result<Recipient> {
+    match Recipient::from_str(arg) {
+        Ok(recipient) => Ok(recipient),
+        Err(_) => Recipient::from_str(&std::fs::read_to_string(arg)?),
+    }
+}
+
+fn read_verifying_key(arg: &str) -> Result<VerifyingKey> {
+    match VerifyingKey::from_str(arg) {
+        Ok(key) => Ok(key),
+        Err(_) => VerifyingKey::from_str(&std::fs::read_to_string(arg)?),
+    }
+}
+
+// 形如 90s、30m、12h、7d 的时长，单位为秒
+fn parse_duration(arg: &str) -> std::result::Result<u64, String> {
+    let unit = match arg.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        _ => return Err("Duration must end with s, m, h or d".to_string()),
+    };
+    let amount: u64 = arg[..arg.len() - 1].parse().map_err(|e| format!("{}", e))?;
+    amount.checked_mul(unit).ok_or_else(|| "Duration is too long".to_string())
+}
+
+// 私钥只允许所有者读写，已有的密钥文件不会被覆盖
+fn write_secret_key(path: &str, secret: &str) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path).map_err(|e| format!("{}: {}", path, e))?;
+    file.write_all(format!("{}\n", secret).as_bytes())?;
+    Ok(())
+}
+
+fn read_identity(path: &str) -> Result<Identity> {
+    Identity::from_str(&std::fs::read_to_string(path)?)
+}
+
 fn open_png(path: &str) -> Result<Png> {
     let  mut file = File::open(path)?;
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer)?;
-    Png::try_from(buffer.as_slice()).map_err(|e| e.into())
+    Png::try_from(buffer.as_slice())
+}
+
+// 读取消息时放过 CRC 错误的辅助块，由纠错码修复其中的数据
+fn open_damaged_png(path: &str) -> Result<Png> {
+    let (png, damaged) = Png::parse_lenient(&std::fs::read(path)?)?;
+    for chunk_type in damaged {
+        eprintln!("Warning: {} chunk in {} has a bad CRC", chunk_type, path);
+    }
+    Ok(png)
 }
 
 
@@ -0,0 +1,73 @@
+use std::fmt;
+
+use crate::png::Png;
+use crate::stego::{text, zlib_slack};
+use crate::Result;
+
+/// Something in a PNG that suggests hidden data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    /// Bytes stored after the end of the zlib stream inside IDAT.
+    ZlibSlack { bytes: usize },
+    /// A text chunk whose text is base64 data.
+    EncodedText { keyword: String, bytes: usize },
+    /// IDAT doesn't hold a complete zlib stream, so it can't be checked for
+    /// trailing data.
+    MalformedImageData { reason: String },
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::ZlibSlack { bytes } => {
+                write!(f, "{} bytes of data after the end of the IDAT zlib stream", bytes)
+            }
+            Finding::EncodedText { keyword, bytes } => {
+                write!(f, "{} bytes of base64 data in the {} text chunk", bytes, keyword)
+            }
+            Finding::MalformedImageData { reason } => write!(f, "Malformed image data: {}", reason),
+        }
+    }
+}
+
+/// Runs every detector against `png`. Damaged image data is reported as a
+/// finding rather than stopping the other detectors.
+pub fn analyze(png: &Png) -> Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+    match zlib_slack::detect(png) {
+        Ok(Some(bytes)) => findings.push(Finding::ZlibSlack { bytes }),
+        Ok(None) => {}
+        Err(e) => findings.push(Finding::MalformedImageData { reason: e.to_string() }),
+    }
+    for (keyword, bytes) in text::detect(png) {
+        findings.push(Finding::EncodedText { keyword, bytes });
+    }
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+
+    #[test]
+    fn test_truncated_image_data_is_a_finding() {
+        let comment = format!("Comment\0{}", "QUJD".repeat(16));
+        let png = Png::from_chunks(vec![
+            Chunk::new(ChunkType::IHDR, Vec::new()),
+            Chunk::new(ChunkType::TEXT, comment.into_bytes()),
+            Chunk::new(ChunkType::IDAT, vec![0x78, 0x9c, 0x63]),
+            Chunk::new(ChunkType::IEND, Vec::new()),
+        ]);
+        let findings = analyze(&png).unwrap();
+        assert!(matches!(findings[0], Finding::MalformedImageData { .. }));
+        assert_eq!(
+            findings[1],
+            Finding::EncodedText {
+                keyword: "Comment".to_string(),
+                bytes: 48
+            }
+        );
+    }
+}
@@ -0,0 +1,606 @@
+//! Animated PNG control chunks (acTL, fcTL, fdAT) and the frame sequence
+//! they describe.
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::idat::{FilterStrategy, Raster};
+use crate::ihdr::{ColorType, Ihdr};
+use crate::optimize;
+use crate::png::Png;
+use crate::{Error, Result};
+
+/// What happens to the frame region before the next frame is rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisposeOp {
+    None = 0,
+    Background = 1,
+    Previous = 2,
+}
+
+/// How the frame is drawn over the output buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendOp {
+    Source = 0,
+    Over = 1,
+}
+
+impl TryFrom<u8> for DisposeOp {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(DisposeOp::None),
+            1 => Ok(DisposeOp::Background),
+            2 => Ok(DisposeOp::Previous),
+            _ => Err(Error::from("Invalid dispose op")),
+        }
+    }
+}
+
+impl TryFrom<u8> for BlendOp {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(BlendOp::Source),
+            1 => Ok(BlendOp::Over),
+            _ => Err(Error::from("Invalid blend op")),
+        }
+    }
+}
+
+/// The decoded contents of an acTL chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationControl {
+    pub num_frames: u32,
+    /// 0 means loop forever.
+    pub num_plays: u32,
+}
+
+impl AnimationControl {
+    pub const DATA_SIZE: usize = 8;
+
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = Vec::with_capacity(Self::DATA_SIZE);
+        data.extend_from_slice(&self.num_frames.to_be_bytes());
+        data.extend_from_slice(&self.num_plays.to_be_bytes());
+        Chunk::new(ChunkType::ACTL, data)
+    }
+}
+
+impl TryFrom<&Chunk> for AnimationControl {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        if *chunk.chunk_type() != ChunkType::ACTL {
+            return Err(Error::from("Not an acTL chunk"));
+        }
+        let data = chunk.data();
+        if data.len() != Self::DATA_SIZE {
+            return Err(Error::from("Invalid acTL length"));
+        }
+        let num_frames = u32::from_be_bytes(data[0..4].try_into()?);
+        if num_frames == 0 {
+            return Err(Error::from("acTL must declare at least one frame"));
+        }
+        Ok(AnimationControl {
+            num_frames,
+            num_plays: u32::from_be_bytes(data[4..8].try_into()?),
+        })
+    }
+}
+
+/// The decoded contents of an fcTL chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameControl {
+    pub sequence_number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    /// The frame is shown for `delay_num / delay_den` seconds, a
+    /// denominator of 0 meaning 1/100.
+    pub delay_num: u16,
+    pub delay_den: u16,
+    pub dispose_op: DisposeOp,
+    pub blend_op: BlendOp,
+}
+
+impl FrameControl {
+    pub const DATA_SIZE: usize = 26;
+
+    /// Delay in seconds.
+    pub fn delay(&self) -> f64 {
+        let den = if self.delay_den == 0 { 100 } else { self.delay_den };
+        self.delay_num as f64 / den as f64
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = Vec::with_capacity(Self::DATA_SIZE);
+        for value in [self.sequence_number, self.width, self.height, self.x_offset, self.y_offset] {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        data.extend_from_slice(&self.delay_num.to_be_bytes());
+        data.extend_from_slice(&self.delay_den.to_be_bytes());
+        data.push(self.dispose_op as u8);
+        data.push(self.blend_op as u8);
+        Chunk::new(ChunkType::FCTL, data)
+    }
+}
+
+impl TryFrom<&Chunk> for FrameControl {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        if *chunk.chunk_type() != ChunkType::FCTL {
+            return Err(Error::from("Not an fcTL chunk"));
+        }
+        let data = chunk.data();
+        if data.len() != Self::DATA_SIZE {
+            return Err(Error::from("Invalid fcTL length"));
+        }
+        let word = |i: usize| -> Result<u32> { Ok(u32::from_be_bytes(data[i..i + 4].try_into()?)) };
+        let control = FrameControl {
+            sequence_number: word(0)?,
+            width: word(4)?,
+            height: word(8)?,
+            x_offset: word(12)?,
+            y_offset: word(16)?,
+            delay_num: u16::from_be_bytes([data[20], data[21]]),
+            delay_den: u16::from_be_bytes([data[22], data[23]]),
+            dispose_op: DisposeOp::try_from(data[24])?,
+            blend_op: BlendOp::try_from(data[25])?,
+        };
+        if control.width == 0 || control.height == 0 {
+            return Err(Error::from("Frame dimensions must be non-zero"));
+        }
+        Ok(control)
+    }
+}
+
+/// One frame of an animation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub control: FrameControl,
+    /// The frame's zlib stream, from IDAT for the default image or from the
+    /// fdAT chunks with their sequence numbers removed.
+    pub data: Vec<u8>,
+}
+
+/// A parsed and validated APNG frame sequence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Animation {
+    pub frames: Vec<Frame>,
+    /// Number of times to play the animation, 0 for forever.
+    pub loops: u32,
+    /// Whether the default image (IDAT) is the first frame.
+    pub default_image_is_frame: bool,
+}
+
+/// Whether `chunk_type` belongs to a frame: fcTL, fdAT or IDAT.
+pub fn is_frame_chunk(chunk_type: &ChunkType) -> bool {
+    [ChunkType::FCTL, ChunkType::FDAT, ChunkType::IDAT].contains(chunk_type)
+}
+
+/// Parses the animation of `png`. Returns `None` for still images and an
+/// error if the frames are out of sequence or don't fit the canvas.
+pub fn parse(png: &Png) -> Result<Option<Animation>> {
+    let Some(actl) = png.chunk_by_type("acTL") else {
+        return Ok(None);
+    };
+    let actl = AnimationControl::try_from(actl)?;
+    let ihdr = png.ihdr()?;
+
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut next_sequence = 0;
+    let mut seen_idat = false;
+    let mut default_image_is_frame = false;
+    for chunk in png.chunks() {
+        let chunk_type = chunk.chunk_type();
+        if *chunk_type == ChunkType::FCTL {
+            let control = FrameControl::try_from(chunk)?;
+            check_sequence(control.sequence_number, &mut next_sequence)?;
+            if frames.last().is_some_and(|frame| frame.data.is_empty()) {
+                return Err(Error::from(format!("Frame {} has no image data", frames.len() - 1)));
+            }
+            let fits = control.x_offset.checked_add(control.width).is_some_and(|r| r <= ihdr.width)
+                && control.y_offset.checked_add(control.height).is_some_and(|b| b <= ihdr.height);
+            if !fits {
+                return Err(Error::from(format!("Frame {} does not fit the canvas", frames.len())));
+            }
+            if !seen_idat && frames.is_empty() {
+                // 默认图像作为第一帧时必须覆盖整个画布
+                if (control.width, control.height, control.x_offset, control.y_offset) != (ihdr.width, ihdr.height, 0, 0) {
+                    return Err(Error::from("The first frame must cover the whole canvas"));
+                }
+                default_image_is_frame = true;
+            }
+            frames.push(Frame {
+                control,
+                data: Vec::new(),
+            });
+        } else if *chunk_type == ChunkType::IDAT {
+            seen_idat = true;
+            if default_image_is_frame {
+                frames[0].data.extend_from_slice(chunk.data());
+            }
+        } else if *chunk_type == ChunkType::FDAT {
+            let data = chunk.data();
+            if data.len() < 4 {
+                return Err(Error::from("Invalid fdAT length"));
+            }
+            check_sequence(u32::from_be_bytes(data[0..4].try_into()?), &mut next_sequence)?;
+            // 默认图像那一帧的数据只能来自 IDAT
+            let expects_fdat = seen_idat && !(default_image_is_frame && frames.len() == 1);
+            match frames.last_mut() {
+                Some(frame) if expects_fdat => frame.data.extend_from_slice(&data[4..]),
+                _ => return Err(Error::from("fdAT without a preceding fcTL")),
+            }
+        }
+    }
+
+    if frames.last().is_some_and(|frame| frame.data.is_empty()) {
+        return Err(Error::from(format!("Frame {} has no image data", frames.len() - 1)));
+    }
+    if frames.len() != actl.num_frames as usize {
+        return Err(Error::from(format!(
+            "acTL declares {} frames but {} were found",
+            actl.num_frames,
+            frames.len()
+        )));
+    }
+    Ok(Some(Animation {
+        frames,
+        loops: actl.num_plays,
+        default_image_is_frame,
+    }))
+}
+
+// fcTL 和 fdAT 共用一个从 0 开始、没有间隔的序号
+fn check_sequence(sequence_number: u32, next: &mut u32) -> Result<()> {
+    if sequence_number != *next {
+        return Err(Error::from(format!(
+            "Expected sequence number {}, found {}",
+            next, sequence_number
+        )));
+    }
+    *next += 1;
+    Ok(())
+}
+
+/// Decodes every frame and composites it onto the canvas with its dispose
+/// and blend ops applied, giving the images a viewer shows. Frames are RGBA
+/// with 16-bit samples for 16-bit images and 8-bit samples otherwise.
+pub fn render(png: &Png) -> Result<Vec<Raster>> {
+    let animation = parse(png)?.ok_or("Not an animated PNG")?;
+    let ihdr = png.ihdr()?;
+    let palette = png.palette()?;
+    let trns = png.chunk_by_type("tRNS");
+    let output = rgba_ihdr(ihdr.width, ihdr.height, ihdr.bit_depth == 16);
+
+    // 画布在每次播放开始时是全透明的黑色
+    let mut canvas = vec![[0u16; 4]; ihdr.width as usize * ihdr.height as usize];
+    let mut rendered = Vec::with_capacity(animation.frames.len());
+    for (i, frame) in animation.frames.iter().enumerate() {
+        let control = &frame.control;
+        let frame_ihdr = Ihdr {
+            width: control.width,
+            height: control.height,
+            ..ihdr
+        };
+        let pixels = optimize::expand(&Raster::decode_stream(frame_ihdr, &frame.data)?, palette.as_ref(), trns)?;
+        let region: Vec<usize> = (control.y_offset..control.y_offset + control.height)
+            .flat_map(|y| {
+                (control.x_offset..control.x_offset + control.width)
+                    .map(move |x| y as usize * ihdr.width as usize + x as usize)
+            })
+            .collect();
+
+        // 第一帧的 Previous 按 Background 处理
+        let dispose = match control.dispose_op {
+            DisposeOp::Previous if i == 0 => DisposeOp::Background,
+            op => op,
+        };
+        let saved = (dispose == DisposeOp::Previous).then(|| canvas.clone());
+        for (&index, &source) in region.iter().zip(&pixels) {
+            canvas[index] = match control.blend_op {
+                BlendOp::Source => source,
+                BlendOp::Over => over(source, canvas[index]),
+            };
+        }
+        rendered.push(to_raster(&canvas, output)?);
+
+        match saved {
+            Some(saved) => canvas = saved,
+            None if dispose == DisposeOp::Background => region.iter().for_each(|&index| canvas[index] = [0; 4]),
+            None => {}
+        }
+    }
+    Ok(rendered)
+}
+
+/// Builds an animated PNG that shows `images` in order, each for its
+/// `delays` entry of `(numerator, denominator)` seconds, played `loops`
+/// times (0 for forever). Images that share IHDR, PLTE and tRNS keep their
+/// image data, otherwise every frame is converted to RGBA.
+pub fn assemble(images: &[Png], delays: &[(u16, u16)], loops: u32) -> Result<Png> {
+    let first = images.first().ok_or("At least one image is required")?;
+    if delays.len() != images.len() {
+        return Err(Error::from("Every image needs a delay"));
+    }
+    let ihdr = first.ihdr()?;
+    for image in images {
+        if image.chunk_by_type("acTL").is_some() {
+            return Err(Error::from("Images must not be animated themselves"));
+        }
+        let other = image.ihdr()?;
+        if (other.width, other.height) != (ihdr.width, ihdr.height) {
+            return Err(Error::from("All images must have the same dimensions"));
+        }
+    }
+
+    let shared = ["IHDR", "PLTE", "tRNS"]
+        .iter()
+        .all(|t| images.iter().all(|image| image.chunk_by_type(t) == first.chunk_by_type(t)));
+    let (ihdr, streams) = if shared {
+        (ihdr, images.iter().map(Png::image_data).collect())
+    } else {
+        let mut sixteen_bit = false;
+        for image in images {
+            sixteen_bit |= image.ihdr()?.bit_depth == 16;
+        }
+        let output = rgba_ihdr(ihdr.width, ihdr.height, sixteen_bit);
+        let streams = images
+            .iter()
+            .map(|image| {
+                let pixels = optimize::expand(&Raster::decode(image)?, image.palette()?.as_ref(), image.chunk_by_type("tRNS"))?;
+                to_raster(&pixels, output)?.encode(FilterStrategy::Adaptive, 9)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        (output, streams)
+    };
+
+    let mut chunks = vec![ihdr.to_chunk()];
+    // 色彩空间信息取自第一张图片
+    chunks.extend(
+        first
+            .chunks()
+            .iter()
+            .filter(|c| optimize::PRESERVED_CHUNKS.contains(&c.chunk_type().to_string().as_str()))
+            .cloned(),
+    );
+    chunks.push(
+        AnimationControl {
+            num_frames: images.len() as u32,
+            num_plays: loops,
+        }
+        .to_chunk(),
+    );
+    if shared {
+        chunks.extend(first.chunk_by_type("PLTE").cloned());
+        chunks.extend(first.chunk_by_type("tRNS").cloned());
+    }
+
+    let mut sequence_number = 0;
+    for (i, (stream, &(delay_num, delay_den))) in streams.into_iter().zip(delays).enumerate() {
+        let control = FrameControl {
+            sequence_number,
+            width: ihdr.width,
+            height: ihdr.height,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num,
+            delay_den,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        };
+        chunks.push(control.to_chunk());
+        sequence_number += 1;
+        // 第一帧即默认图像，放在 IDAT 中
+        if i == 0 {
+            chunks.push(Chunk::new(ChunkType::IDAT, stream));
+        } else {
+            let mut data = sequence_number.to_be_bytes().to_vec();
+            data.extend_from_slice(&stream);
+            chunks.push(Chunk::new(ChunkType::FDAT, data));
+            sequence_number += 1;
+        }
+    }
+    chunks.push(Chunk::new(ChunkType::IEND, Vec::new()));
+    Ok(Png::from_chunks(chunks))
+}
+
+fn rgba_ihdr(width: u32, height: u32, sixteen_bit: bool) -> Ihdr {
+    Ihdr {
+        width,
+        height,
+        bit_depth: if sixteen_bit { 16 } else { 8 },
+        color_type: ColorType::Rgba,
+        interlaced: false,
+    }
+}
+
+// 将 16 位 RGBA 像素写入 8 位或 16 位的 RGBA raster
+fn to_raster(pixels: &[[u16; 4]], ihdr: Ihdr) -> Result<Raster> {
+    let data = if ihdr.bit_depth == 16 {
+        pixels.iter().flatten().flat_map(|v| v.to_be_bytes()).collect()
+    } else {
+        pixels.iter().flatten().map(|&v| ((v as u32 + 128) / 257) as u8).collect()
+    };
+    Raster::new(ihdr, data)
+}
+
+// APNG_BLEND_OP_OVER：非预乘 alpha 的 over 合成
+fn over(source: [u16; 4], target: [u16; 4]) -> [u16; 4] {
+    const MAX: u64 = 65535;
+    let (sa, ta) = (source[3] as u64, target[3] as u64);
+    if sa == MAX || ta == 0 {
+        return source;
+    }
+    if sa == 0 {
+        return target;
+    }
+    let alpha = sa * MAX + ta * (MAX - sa);
+    std::array::from_fn(|c| {
+        if c == 3 {
+            (alpha / MAX) as u16
+        } else {
+            ((source[c] as u64 * sa * MAX + target[c] as u64 * ta * (MAX - sa)) / alpha) as u16
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn control(sequence_number: u32, width: u32) -> FrameControl {
+        FrameControl {
+            sequence_number,
+            width,
+            height: 4,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        }
+    }
+
+    fn fdat(sequence_number: u32, data: &[u8]) -> Chunk {
+        let mut bytes = sequence_number.to_be_bytes().to_vec();
+        bytes.extend_from_slice(data);
+        Chunk::new(ChunkType::FDAT, bytes)
+    }
+
+    fn animated() -> Vec<Chunk> {
+        let ihdr = Ihdr {
+            width: 4,
+            height: 4,
+            bit_depth: 8,
+            color_type: ColorType::Rgba,
+            interlaced: false,
+        };
+        vec![
+            ihdr.to_chunk(),
+            AnimationControl { num_frames: 3, num_plays: 0 }.to_chunk(),
+            control(0, 4).to_chunk(),
+            Chunk::new(ChunkType::IDAT, vec![1, 2]),
+            Chunk::new(ChunkType::IDAT, vec![3]),
+            control(1, 2).to_chunk(),
+            fdat(2, &[4, 5]),
+            fdat(3, &[6]),
+            control(4, 4).to_chunk(),
+            fdat(5, &[7]),
+            Chunk::new(ChunkType::IEND, Vec::new()),
+        ]
+    }
+
+    #[test]
+    fn test_parse_animation() {
+        let animation = parse(&Png::from_chunks(animated())).unwrap().unwrap();
+        assert_eq!(animation.loops, 0);
+        assert!(animation.default_image_is_frame);
+        let data: Vec<&[u8]> = animation.frames.iter().map(|f| f.data.as_slice()).collect();
+        assert_eq!(data, vec![&[1, 2, 3][..], &[4, 5, 6], &[7]]);
+        assert_eq!(animation.frames[1].control.width, 2);
+        assert_eq!(animation.frames[1].control.delay(), 0.1);
+
+        let chunk = Chunk::new(ChunkType::from_str("tEXt").unwrap(), Vec::new());
+        let mut still = animated();
+        still.retain(|c| !is_frame_chunk(c.chunk_type()) || *c.chunk_type() == ChunkType::IDAT);
+        still.retain(|c| *c.chunk_type() != ChunkType::ACTL);
+        still.push(chunk);
+        assert_eq!(parse(&Png::from_chunks(still)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_rejects_bad_sequences() {
+        let mut chunks = animated();
+        chunks.swap(6, 7);
+        let error = parse(&Png::from_chunks(chunks)).unwrap_err();
+        assert_eq!(error.to_string(), "Expected sequence number 2, found 3");
+
+        let mut chunks = animated();
+        chunks[1] = AnimationControl { num_frames: 2, num_plays: 1 }.to_chunk();
+        assert!(parse(&Png::from_chunks(chunks)).is_err());
+
+        let mut chunks = animated();
+        chunks[5] = FrameControl { x_offset: 3, ..control(1, 2) }.to_chunk();
+        assert_eq!(
+            parse(&Png::from_chunks(chunks)).unwrap_err().to_string(),
+            "Frame 1 does not fit the canvas"
+        );
+    }
+
+    fn rgba(width: u32, pixels: &[[u8; 4]]) -> Raster {
+        let ihdr = rgba_ihdr(width, pixels.len() as u32 / width, false);
+        Raster::new(ihdr, pixels.concat()).unwrap()
+    }
+
+    #[test]
+    fn test_render_applies_dispose_and_blend() {
+        let (red, blue, green) = ([255, 0, 0, 255], [0, 0, 255, 128], [0, 255, 0, 255]);
+        let stream = |pixels: &[[u8; 4]]| rgba(1, pixels).encode(FilterStrategy::Adaptive, 6).unwrap();
+        let frame = |sequence_number, x_offset, dispose_op, blend_op| {
+            FrameControl {
+                sequence_number,
+                width: 1,
+                height: 1,
+                x_offset,
+                y_offset: 0,
+                delay_num: 1,
+                delay_den: 10,
+                dispose_op,
+                blend_op,
+            }
+            .to_chunk()
+        };
+        let chunks = vec![
+            rgba_ihdr(2, 1, false).to_chunk(),
+            AnimationControl { num_frames: 3, num_plays: 0 }.to_chunk(),
+            FrameControl { width: 2, ..FrameControl::try_from(&frame(0, 0, DisposeOp::None, BlendOp::Source)).unwrap() }.to_chunk(),
+            Chunk::new(ChunkType::IDAT, rgba(2, &[red, [0; 4]]).encode(FilterStrategy::Adaptive, 6).unwrap()),
+            frame(1, 0, DisposeOp::Previous, BlendOp::Over),
+            fdat(2, &stream(&[blue])),
+            frame(3, 1, DisposeOp::Background, BlendOp::Over),
+            fdat(4, &stream(&[green])),
+            Chunk::new(ChunkType::IEND, Vec::new()),
+        ];
+        let rendered = render(&Png::from_chunks(chunks)).unwrap();
+        assert_eq!(rendered[0], rgba(2, &[red, [0; 4]]));
+        assert_eq!(rendered[1], rgba(2, &[[127, 0, 128, 255], [0; 4]]));
+        assert_eq!(rendered[2], rgba(2, &[red, green]));
+    }
+
+    #[test]
+    fn test_assemble_and_render() {
+        let frames = [
+            rgba(2, &[[1, 2, 3, 255], [4, 5, 6, 255], [7, 8, 9, 0], [10, 11, 12, 255]]),
+            rgba(2, &[[9, 9, 9, 255], [0, 0, 0, 0], [5, 5, 5, 128], [1, 1, 1, 255]]),
+        ];
+        let images: Vec<Png> = frames.iter().map(|f| f.to_png().unwrap()).collect();
+        let png = assemble(&images, &[(1, 10), (1, 5)], 2).unwrap();
+        let animation = png.animation().unwrap().unwrap();
+        assert_eq!(animation.loops, 2);
+        assert!(animation.default_image_is_frame);
+        assert_eq!(animation.frames[1].control.delay(), 0.2);
+        assert_eq!(render(&png).unwrap(), frames);
+
+        // 格式不同的图片统一转换为 RGBA
+        let gray_ihdr = Ihdr {
+            color_type: ColorType::Grayscale,
+            ..rgba_ihdr(2, 2, false)
+        };
+        let gray = Raster::new(gray_ihdr, vec![0, 85, 170, 255]).unwrap();
+        let images = vec![frames[0].to_png().unwrap(), gray.to_png().unwrap()];
+        let png = assemble(&images, &[(1, 10), (1, 10)], 0).unwrap();
+        let expected = rgba(2, &[[0, 0, 0, 255], [85, 85, 85, 255], [170, 170, 170, 255], [255, 255, 255, 255]]);
+        assert_eq!(render(&png).unwrap(), vec![frames[0].clone(), expected]);
+
+        assert!(assemble(&images, &[(1, 10)], 0).is_err());
+        assert!(assemble(&[frames[0].to_png().unwrap(), rgba(1, &[[0; 4]]).to_png().unwrap()], &[(1, 10); 2], 0).is_err());
+    }
+}
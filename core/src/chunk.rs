@@ -1,7 +1,7 @@
 use std::fmt;
 use crate::{chunk_type::ChunkType, Error, Result};
 
-#[derive(Debug, PartialEq,)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Chunk {
     chunk_type: ChunkType,
     data: Vec<u8>,
@@ -28,8 +28,7 @@ impl Chunk {
         &self.chunk_type
     }
 
-    #[allow(dead_code)]
-    fn data(&self) -> &[u8]{ 
+    pub fn data(&self) -> &[u8]{ 
         &self.data 
     }
 // 规范中只计算 chunk type 和 data 部分的 CRC，不包括 length 和 CRC 字段
@@ -53,10 +52,10 @@ impl Chunk {
 
 }
 
-impl TryFrom<&[u8]> for Chunk {
-    type Error = Error;
-
-    fn try_from(bytes: &[u8]) -> Result<Self> {
+impl Chunk {
+    /// Parses the chunk at the start of `bytes` without rejecting a CRC
+    /// mismatch. Returns the chunk and whether its CRC matched.
+    pub(crate) fn parse(bytes: &[u8]) -> Result<(Chunk, bool)> {
         if bytes.len() < Chunk::METADATA_SIZE {
             return Err(Error::from("Chunk data is too short"));
         }
@@ -73,6 +72,10 @@ impl TryFrom<&[u8]> for Chunk {
         if !chunk_type.is_valid() {
             return Err(Error::from("Invalid chunk type"));
         }
+        // 长度字段损坏时可能超出剩余数据
+        if rest.len() < data_length as usize + Chunk::CRC_SIZE {
+            return Err(Error::from("Chunk data is too short"));
+        }
         let (data, rest) = rest.split_at(data_length as usize);
         let (crc_bytes, _) = rest.split_at(Chunk::CRC_SIZE);
 
@@ -83,11 +86,19 @@ impl TryFrom<&[u8]> for Chunk {
 
         let actual_crc = new.crc();
         let expected_crc = u32::from_be_bytes(crc_bytes.try_into()?);
+        Ok((new, actual_crc == expected_crc))
+    }
+}
+
+impl TryFrom<&[u8]> for Chunk {
+    type Error = Error;
 
-        if actual_crc != expected_crc {
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        let (chunk, crc_matches) = Chunk::parse(bytes)?;
+        if !crc_matches {
             return Err(Error::from("Invalid CRC"));
         }
-        Ok(new)
+        Ok(chunk)
     }
     
 }
@@ -1,4 +1,5 @@
 use crate::{Error, Result};
+use rand::Rng;
 use std::convert::TryFrom;
 use std::fmt;
 use std::str::FromStr;
@@ -9,6 +10,53 @@ pub struct ChunkType {
 }
 
 impl ChunkType {
+    pub const IHDR: ChunkType = ChunkType::new(*b"IHDR");
+    pub const PLTE: ChunkType = ChunkType::new(*b"PLTE");
+    pub const IDAT: ChunkType = ChunkType::new(*b"IDAT");
+    pub const IEND: ChunkType = ChunkType::new(*b"IEND");
+    pub const TRNS: ChunkType = ChunkType::new(*b"tRNS");
+    pub const TEXT: ChunkType = ChunkType::new(*b"tEXt");
+    pub const ZTXT: ChunkType = ChunkType::new(*b"zTXt");
+    pub const ITXT: ChunkType = ChunkType::new(*b"iTXt");
+    pub const ACTL: ChunkType = ChunkType::new(*b"acTL");
+    pub const FCTL: ChunkType = ChunkType::new(*b"fcTL");
+    pub const FDAT: ChunkType = ChunkType::new(*b"fdAT");
+
+    /// Builds a chunk type in const context, failing to compile (or
+    /// panicking at run time) if a byte is not an ASCII letter.
+    pub const fn new(bytes: [u8; 4]) -> ChunkType {
+        let mut i = 0;
+        while i < 4 {
+            assert!(bytes[i].is_ascii_alphabetic(), "Invalid byte in chunk");
+            i += 1;
+        }
+        ChunkType { data: bytes }
+    }
+
+    /// Same letters with the property bits set as given and the reserved
+    /// bit cleared.
+    pub fn with_properties(&self, critical: bool, public: bool, safe_to_copy: bool) -> ChunkType {
+        // 大写表示关键、公有、不可安全复制
+        let case = |byte: u8, upper: bool| {
+            if upper {
+                byte.to_ascii_uppercase()
+            } else {
+                byte.to_ascii_lowercase()
+            }
+        };
+        let [a, b, c, d] = self.data;
+        ChunkType {
+            data: [case(a, critical), case(b, public), case(c, true), case(d, !safe_to_copy)],
+        }
+    }
+
+    /// A random ancillary, private, safe-to-copy chunk type, the kind used
+    /// for messages.
+    pub fn random_private_ancillary<R: Rng + ?Sized>(rng: &mut R) -> ChunkType {
+        let letters: [u8; 4] = std::array::from_fn(|_| rng.gen_range(b'a'..=b'z'));
+        ChunkType { data: letters }.with_properties(false, false, true)
+    }
+
     pub fn bytes(&self) -> [u8; 4] {
         self.data
     }
@@ -41,15 +89,18 @@ impl ChunkType {
     }
 
     fn is_valid_byte(byte: u8) -> bool {
-        byte >= b'a' && byte <= b'z' || (byte >= b'A' && byte <= b'Z')
+        byte.is_ascii_alphabetic()
     }
 }
 
+/// Only rejects bytes that are not ASCII letters. A set reserved bit is
+/// accepted so it can be inspected with [`ChunkType::is_valid`];
+/// `Chunk::try_from` still rejects such types when reading a file.
 impl TryFrom<[u8; 4]> for ChunkType {
     type Error = Error;
     fn try_from(bytes: [u8; 4]) -> Result<ChunkType> {
         let validated =  bytes.iter().all(|&byte| Self::is_valid_byte(byte));
-        if !ChunkType::is_reserved_bit_valid_bytes(bytes) ||  !validated {
+        if !validated {
             return Err(Error::from("Invalid byte in chunk"));
         }
         Ok(ChunkType { data: bytes })
@@ -74,7 +125,7 @@ impl FromStr for ChunkType {
         let data: [u8; 4] = bytes
             .try_into()
             .map_err(|_| Error::from("ChunkType must be 4 bytes long"))?;
-        Ok(ChunkType::try_from(data)?)
+        ChunkType::try_from(data)
     }
 }
 
@@ -168,6 +219,39 @@ mod tests {
         assert_eq!(&chunk.to_string(), "RuSt");
     }
 
+    #[test]
+    pub fn test_chunk_type_with_properties() {
+        let chunk = ChunkType::from_str("rust").unwrap();
+        assert_eq!(chunk.with_properties(false, false, true).to_string(), "ruSt");
+        assert_eq!(chunk.with_properties(true, true, false).to_string(), "RUST");
+        let chunk = chunk.with_properties(true, false, true);
+        assert!(chunk.is_valid() && chunk.is_critical() && !chunk.is_public() && chunk.is_safe_to_copy());
+    }
+
+    #[test]
+    pub fn test_random_private_ancillary() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let chunk = ChunkType::random_private_ancillary(&mut rng);
+            assert!(chunk.is_valid());
+            assert!(!chunk.is_critical() && !chunk.is_public() && chunk.is_safe_to_copy());
+        }
+    }
+
+    #[test]
+    pub fn test_const_chunk_types() {
+        const CUSTOM: ChunkType = ChunkType::new(*b"ruSt");
+        assert_eq!(CUSTOM, ChunkType::from_str("ruSt").unwrap());
+        assert_eq!(ChunkType::IHDR.to_string(), "IHDR");
+        assert!(ChunkType::IEND.is_critical() && !ChunkType::TEXT.is_critical());
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn test_const_chunk_type_rejects_invalid_bytes() {
+        ChunkType::new(*b"Ru1t");
+    }
+
     #[test]
     pub fn test_chunk_type_trait_impls() {
         let chunk_type_1: ChunkType = TryFrom::try_from([82, 117, 83, 116]).unwrap();
@@ -0,0 +1,326 @@
+use crate::gf256;
+use crate::{Error, Result};
+
+// 同样以 0x89 开头，和 envelope、fragment 区分开
+pub const MAGIC: [u8; 4] = [0x89, b'P', b'C', b'R'];
+/// Length of a Reed-Solomon codeword, data and parity together.
+pub const BLOCK_SIZE: usize = 255;
+const HEADER_SIZE: usize = 4 + 1 + 4;
+// 头部存三份，按字节多数表决，否则头部损坏就无法纠错
+const HEADER_COPIES: usize = 3;
+
+/// Payload recovered by [`detect`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Corrected {
+    pub data: Vec<u8>,
+    /// Number of damaged bytes that were repaired.
+    pub errors: usize,
+}
+
+/// Parity bytes per block needed to repair up to `fraction` damaged bytes
+/// of every block.
+pub fn parity_for(fraction: f64) -> Result<u8> {
+    let errors = (fraction * BLOCK_SIZE as f64).ceil();
+    if fraction.is_nan() || fraction <= 0.0 || errors * 2.0 >= BLOCK_SIZE as f64 {
+        return Err(Error::from("Correctable fraction must be above 0 and below 0.5"));
+    }
+    Ok(errors as u8 * 2)
+}
+
+/// Protects `data` with Reed-Solomon codes over GF(256), adding `parity`
+/// bytes to every block of `255 - parity` data bytes. Up to `parity / 2`
+/// damaged bytes per block can be repaired.
+///
+/// Layout: the header `magic | parity | data length (u32)` three times, then
+/// the blocks interleaved byte by byte so that a burst of damage is spread
+/// over many blocks.
+pub fn encode(data: &[u8], parity: u8) -> Result<Vec<u8>> {
+    if parity == 0 || parity as usize >= BLOCK_SIZE || !parity.is_multiple_of(2) {
+        return Err(Error::from("Parity must be an even number of bytes below 255"));
+    }
+    let length = u32::try_from(data.len()).map_err(|_| "Data is too large")?;
+    let data_size = BLOCK_SIZE - parity as usize;
+    let blocks = data.len().div_ceil(data_size).max(1);
+
+    let generator = generator(parity as usize);
+    let mut codewords = Vec::with_capacity(blocks);
+    for b in 0..blocks {
+        let mut block = vec![0u8; data_size];
+        let start = (b * data_size).min(data.len());
+        let end = ((b + 1) * data_size).min(data.len());
+        block[..end - start].copy_from_slice(&data[start..end]);
+        codewords.push(encode_block(&block, &generator));
+    }
+
+    let mut header = MAGIC.to_vec();
+    header.push(parity);
+    header.extend_from_slice(&length.to_be_bytes());
+    let mut frame = header.repeat(HEADER_COPIES);
+    for i in 0..BLOCK_SIZE {
+        frame.extend(codewords.iter().map(|codeword| codeword[i]));
+    }
+    Ok(frame)
+}
+
+/// Decodes a frame written by [`encode`], repairing damaged bytes. Returns
+/// `None` if `frame` isn't protected, and an error if the damage exceeds
+/// what the parity can repair.
+pub fn detect(frame: &[u8]) -> Result<Option<Corrected>> {
+    if frame.len() < HEADER_SIZE * HEADER_COPIES {
+        return Ok(None);
+    }
+    let header: Vec<u8> = (0..HEADER_SIZE)
+        .map(|i| {
+            let copies: Vec<u8> = (0..HEADER_COPIES).map(|c| frame[c * HEADER_SIZE + i]).collect();
+            if copies[1] == copies[2] { copies[1] } else { copies[0] }
+        })
+        .collect();
+    if header[..4] != MAGIC {
+        return Ok(None);
+    }
+    let parity = header[4] as usize;
+    let length = u32::from_be_bytes(header[5..9].try_into()?) as usize;
+    if parity == 0 || parity >= BLOCK_SIZE {
+        return Err(Error::from("Invalid error correction header"));
+    }
+    let data_size = BLOCK_SIZE - parity;
+    let blocks = length.div_ceil(data_size).max(1);
+    let body = &frame[HEADER_SIZE * HEADER_COPIES..];
+    if body.len() != blocks * BLOCK_SIZE {
+        return Err(Error::from("Error correction frame has the wrong length"));
+    }
+
+    let mut data = Vec::with_capacity(blocks * data_size);
+    let mut errors = 0;
+    for b in 0..blocks {
+        let mut codeword: Vec<u8> = (0..BLOCK_SIZE).map(|i| body[i * blocks + b]).collect();
+        errors += correct_block(&mut codeword, parity)
+            .map_err(|e| Error::from(format!("Block {} of {}: {}", b + 1, blocks, e)))?;
+        data.extend_from_slice(&codeword[..data_size]);
+    }
+    data.truncate(length);
+    Ok(Some(Corrected { data, errors }))
+}
+
+// 多项式均按最高次项在前存放
+fn poly_mul(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let mut r = vec![0u8; p.len() + q.len() - 1];
+    for (i, &a) in p.iter().enumerate() {
+        for (j, &b) in q.iter().enumerate() {
+            r[i + j] ^= gf256::mul(a, b);
+        }
+    }
+    r
+}
+
+fn poly_add(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let mut r = vec![0u8; p.len().max(q.len())];
+    let offset = r.len() - p.len();
+    r[offset..].copy_from_slice(p);
+    let offset = r.len() - q.len();
+    for (i, &c) in q.iter().enumerate() {
+        r[offset + i] ^= c;
+    }
+    r
+}
+
+fn poly_scale(p: &[u8], x: u8) -> Vec<u8> {
+    p.iter().map(|&c| gf256::mul(c, x)).collect()
+}
+
+// g(x) = (x - α^0)(x - α^1)...(x - α^(parity-1))
+fn generator(parity: usize) -> Vec<u8> {
+    (0..parity).fold(vec![1], |g, i| poly_mul(&g, &[1, gf256::exp(i as isize)]))
+}
+
+fn encode_block(data: &[u8], generator: &[u8]) -> Vec<u8> {
+    // 系统码：数据后面接上 data(x) * x^parity 除以 g(x) 的余数
+    let parity = generator.len() - 1;
+    let mut codeword = data.to_vec();
+    codeword.resize(data.len() + parity, 0);
+    for i in 0..data.len() {
+        let coef = codeword[i];
+        if coef != 0 {
+            for (j, &g) in generator.iter().enumerate().skip(1) {
+                codeword[i + j] ^= gf256::mul(g, coef);
+            }
+        }
+    }
+    codeword[..data.len()].copy_from_slice(data);
+    codeword
+}
+
+// 返回修正的字节数
+fn correct_block(codeword: &mut [u8], parity: usize) -> Result<usize> {
+    let syndromes: Vec<u8> = (0..parity)
+        .map(|i| gf256::eval(codeword, gf256::exp(i as isize)))
+        .collect();
+    if syndromes.iter().all(|&s| s == 0) {
+        return Ok(0);
+    }
+
+    // Berlekamp-Massey 求错误位置多项式
+    let mut locator = vec![1u8];
+    let mut previous = vec![1u8];
+    for k in 0..parity {
+        let mut delta = syndromes[k];
+        for j in 1..locator.len().min(k + 1) {
+            delta ^= gf256::mul(locator[locator.len() - 1 - j], syndromes[k - j]);
+        }
+        previous.push(0);
+        if delta != 0 {
+            if previous.len() > locator.len() {
+                let next = poly_scale(&previous, delta);
+                previous = poly_scale(&locator, gf256::div(1, delta));
+                locator = next;
+            }
+            locator = poly_add(&locator, &poly_scale(&previous, delta));
+        }
+    }
+    let first = locator.iter().position(|&c| c != 0).unwrap_or(locator.len());
+    let locator = &locator[first..];
+    let count = locator.len().saturating_sub(1);
+    if count == 0 || count * 2 > parity {
+        return Err(Error::from("too many errors to correct"));
+    }
+
+    // Chien 搜索：位置多项式（低次在前）在 α^i 处为零时，第 n-1-i 个字节出错
+    let reversed: Vec<u8> = locator.iter().rev().copied().collect();
+    let n = codeword.len();
+    let positions: Vec<usize> = (0..n)
+        .filter(|&i| gf256::eval(&reversed, gf256::exp(i as isize)) == 0)
+        .map(|i| n - 1 - i)
+        .collect();
+    if positions.len() != count {
+        return Err(Error::from("too many errors to correct"));
+    }
+
+    // Forney 算法求错误值
+    let powers: Vec<isize> = positions.iter().map(|&p| (n - 1 - p) as isize).collect();
+    let errata = powers
+        .iter()
+        .fold(vec![1u8], |acc, &power| poly_mul(&acc, &[gf256::exp(power), 1]));
+    // Ω(x) = S(x)Λ(x) mod x^(错误数+1)，S(x) 的常数项为 0
+    let mut shifted_syndromes: Vec<u8> = syndromes.iter().rev().copied().collect();
+    shifted_syndromes.push(0);
+    let product = poly_mul(&shifted_syndromes, &errata);
+    let evaluator = &product[product.len() - errata.len()..];
+    for (i, &position) in positions.iter().enumerate() {
+        let x = gf256::exp(powers[i]);
+        let x_inv = gf256::exp(-powers[i]);
+        let derivative = powers
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| j != i)
+            .fold(1, |acc, (_, &power)| gf256::mul(acc, 1 ^ gf256::mul(x_inv, gf256::exp(power))));
+        let y = gf256::mul(x, gf256::eval(evaluator, x_inv));
+        codeword[position] ^= gf256::div(y, derivative);
+    }
+
+    let remaining = (0..parity).any(|i| gf256::eval(codeword, gf256::exp(i as isize)) != 0);
+    if remaining {
+        return Err(Error::from("too many errors to correct"));
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::fragment;
+    use crate::png::Png;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use std::str::FromStr;
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 256) as u8).collect()
+    }
+
+    #[test]
+    fn test_round_trip_without_damage() {
+        let frame = encode(&payload(1000), 32).unwrap();
+        assert_eq!(frame.len(), 27 + 5 * BLOCK_SIZE);
+        let corrected = detect(&frame).unwrap().unwrap();
+        assert_eq!(corrected.data, payload(1000));
+        assert_eq!(corrected.errors, 0);
+        assert_eq!(detect(b"Hello, world! Hello, world! Hello").unwrap(), None);
+    }
+
+    #[test]
+    fn test_corrects_random_damage() {
+        let mut rng = ChaCha20Rng::seed_from_u64(7);
+        let parity = parity_for(0.05).unwrap();
+        assert_eq!(parity, 26);
+        for len in [0, 1, 228, 229, 1500] {
+            let mut frame = encode(&payload(len), parity).unwrap();
+            let blocks = (frame.len() - 27) / BLOCK_SIZE;
+            // 包括一份头部在内，每个块损坏 13 个字节
+            for b in 0..blocks {
+                let mut damaged = Vec::new();
+                while damaged.len() < 13 {
+                    let i = rng.gen_range(0..BLOCK_SIZE);
+                    if !damaged.contains(&i) {
+                        damaged.push(i);
+                        frame[27 + i * blocks + b] ^= rng.gen_range(1..=255u8);
+                    }
+                }
+            }
+            frame[3] ^= 0xff;
+            let corrected = detect(&frame).unwrap().unwrap();
+            assert_eq!(corrected.data, payload(len));
+            assert_eq!(corrected.errors, 13 * blocks);
+        }
+    }
+
+    #[test]
+    fn test_corrects_burst_damage() {
+        let mut frame = encode(&payload(2000), 16).unwrap();
+        // 连续 60 个字节被覆盖，分散到 9 个块，每块最多 7 个
+        for byte in frame[500..560].iter_mut() {
+            *byte = 0;
+        }
+        let corrected = detect(&frame).unwrap().unwrap();
+        assert_eq!(corrected.data, payload(2000));
+    }
+
+    #[test]
+    fn test_damaged_png_file() {
+        let chunk = |t: &str, data: Vec<u8>| Chunk::new(ChunkType::from_str(t).unwrap(), data);
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", Vec::new()),
+            chunk("IDAT", Vec::new()),
+            chunk("ruSt", encode(&payload(300), 16).unwrap()),
+            chunk("IEND", Vec::new()),
+        ]);
+        let mut bytes = png.as_bytes();
+        // 文件头和两个空块之后就是 ruSt 的数据，再跳过第一份纠错头
+        let start = 8 + 12 + 12 + 8;
+        bytes[start] ^= 0x5a;
+        for i in (27..237).step_by(21) {
+            bytes[start + i] ^= 0x5a;
+        }
+        assert!(Png::try_from(bytes.as_slice()).is_err());
+
+        let (png, damaged) = Png::parse_lenient(&bytes).unwrap();
+        assert_eq!(damaged, vec![ChunkType::from_str("ruSt").unwrap()]);
+        let frame = fragment::extract(&png, "ruSt").unwrap();
+        let corrected = detect(&frame).unwrap().unwrap();
+        assert_eq!(corrected.data, payload(300));
+        assert_eq!(corrected.errors, 10);
+    }
+
+    #[test]
+    fn test_too_much_damage() {
+        let mut frame = encode(&payload(100), 4).unwrap();
+        for i in 0..5 {
+            frame[27 + i * 40] ^= 0x55;
+        }
+        assert!(detect(&frame).is_err());
+        assert!(encode(b"x", 3).is_err());
+        assert!(parity_for(0.5).is_err());
+        assert!(parity_for(0.0).is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore;
+use zeroize::Zeroizing;
+
+use crate::{Error, Result};
+
+pub const SALT_SIZE: usize = 16;
+pub const NONCE_SIZE: usize = 12;
+pub const TAG_SIZE: usize = 16;
+/// Bytes added to the plaintext by [`seal`].
+pub const OVERHEAD: usize = SALT_SIZE + NONCE_SIZE + TAG_SIZE;
+
+// Argon2id 参数固定下来，否则无法从旧数据推导出同样的密钥
+const MEMORY_KIB: u32 = 19 * 1024;
+const ITERATIONS: u32 = 2;
+const PARALLELISM: u32 = 1;
+
+/// Encrypts `plaintext` with a key derived from `passphrase`.
+/// `associated_data` is authenticated but not encrypted or stored; [`open`]
+/// must be given the same bytes.
+///
+/// The result is laid out as `salt | nonce | ciphertext | tag`, so it can be
+/// stored as chunk data on its own.
+pub fn seal(passphrase: &str, plaintext: &[u8], associated_data: &[u8]) -> Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_SIZE];
+    let mut nonce = [0u8; NONCE_SIZE];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let key = derive_key(passphrase, &salt)?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key[..]));
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: associated_data })
+        .map_err(|_| Error::from("Encryption failed"))?;
+
+    let mut sealed = Vec::with_capacity(OVERHEAD + plaintext.len());
+    sealed.extend_from_slice(&salt);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Decrypts data produced by [`seal`]. Fails if the passphrase is wrong or
+/// the data or associated data was modified.
+pub fn open(passphrase: &str, sealed: &[u8], associated_data: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < OVERHEAD {
+        return Err(Error::from("Encrypted data is too short"));
+    }
+    let (salt, rest) = sealed.split_at(SALT_SIZE);
+    let (nonce, ciphertext) = rest.split_at(NONCE_SIZE);
+
+    let key = derive_key(passphrase, salt)?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key[..]));
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: associated_data })
+        .map_err(|_| Error::from("Decryption failed: wrong passphrase or corrupted data"))
+}
+
+pub(crate) fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+    let params = Params::new(MEMORY_KIB, ITERATIONS, PARALLELISM, Some(32))
+        .map_err(|e| Error::from(e.to_string()))?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key[..])
+        .map_err(|e| Error::from(e.to_string()))?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() {
+        let sealed = seal("hunter2", b"attack at dawn", b"").unwrap();
+        assert_eq!(sealed.len(), OVERHEAD + 14);
+        assert!(!sealed.windows(6).any(|w| w == b"attack"));
+        assert_eq!(open("hunter2", &sealed, b"").unwrap(), b"attack at dawn");
+    }
+
+    #[test]
+    fn test_open_with_wrong_passphrase() {
+        let sealed = seal("hunter2", b"attack at dawn", b"").unwrap();
+        assert!(open("hunter3", &sealed, b"").is_err());
+    }
+
+    #[test]
+    fn test_open_tampered() {
+        let mut sealed = seal("hunter2", b"attack at dawn", b"").unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(open("hunter2", &sealed, b"").is_err());
+        assert!(open("hunter2", &sealed[..OVERHEAD - 1], b"").is_err());
+    }
+
+    #[test]
+    fn test_open_with_other_associated_data() {
+        let sealed = seal("hunter2", b"attack at dawn", b"header").unwrap();
+        assert_eq!(open("hunter2", &sealed, b"header").unwrap(), b"attack at dawn");
+        assert!(open("hunter2", &sealed, b"headex").is_err());
+    }
+}
@@ -0,0 +1,697 @@
+use std::fmt;
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::chunk_type::ChunkType;
+use crate::idat::{deflate, inflate};
+use crate::png::Png;
+use crate::recipient::{self, Identity, Recipient};
+use crate::shamir::ShareInfo;
+use crate::{ecc, encrypt, fragment, Error, Result};
+
+/// Content type of plain text messages.
+pub const TEXT: &str = "text/plain";
+/// Content type of data whose type is unknown, e.g. legacy raw chunks.
+pub const BINARY: &str = "application/octet-stream";
+/// Largest message body a compressed envelope may expand to.
+pub const MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+/// Guesses a MIME type from the extension of `filename`.
+pub fn content_type_for(filename: &str) -> &'static str {
+    let extension = match filename.rsplit_once('.') {
+        Some((_, extension)) => extension.to_ascii_lowercase(),
+        None => return BINARY,
+    };
+    match extension.as_str() {
+        "txt" | "md" | "log" => TEXT,
+        "json" => "application/json",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "xml" => "application/xml",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        _ => BINARY,
+    }
+}
+
+/// Optional descriptive fields stored in the clear, so they can be listed
+/// without decrypting the message.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// Unix time the message was written.
+    pub created: Option<u64>,
+    /// Unix time after which the message should no longer be read.
+    pub expires: Option<u64>,
+    /// Author or label of the message.
+    pub label: Option<String>,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Metadata::default()
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|expires| expires <= now())
+    }
+}
+
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut fields = Vec::new();
+        if let Some(label) = &self.label {
+            fields.push(format!("label: {}", label));
+        }
+        if let Some(created) = self.created {
+            fields.push(format!("created: {}", format_timestamp(created)));
+        }
+        if let Some(expires) = self.expires {
+            let state = if self.is_expired() { "expired" } else { "expires" };
+            fields.push(format!("{}: {}", state, format_timestamp(expires)));
+        }
+        write!(f, "{}", fields.join(", "))
+    }
+}
+
+/// Current Unix time in seconds.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Formats a Unix time as `YYYY-MM-DD HH:MM:SS UTC`.
+pub fn format_timestamp(secs: u64) -> String {
+    // 按公历从 1970-01-01 起的天数换算年月日
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    let time = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Framing stored around every message so decoders can tell how it was
+/// written.
+///
+/// Layout: `magic | version | flags | content type length (u8) | content
+/// type | filename length (u8) | filename | [share info] | [metadata] | body
+/// length (u32) | body`, where the share info is only present with
+/// [`Envelope::SHARE`] and the metadata `created (u64) | expires (u64) |
+/// label length (u8) | label` only with [`Envelope::METADATA`], 0 marking an
+/// unset time.
+/// The header is never encrypted; with [`Envelope::ENCRYPTED`] only the body
+/// is, and everything before the body length except [`Envelope::SIGNED`] is
+/// authenticated with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    pub version: u8,
+    pub flags: u8,
+    pub content_type: String,
+    pub filename: Option<String>,
+    /// Set when the body is one share of a secret split with
+    /// [`crate::shamir`].
+    pub share: Option<ShareInfo>,
+    pub metadata: Metadata,
+    pub body: Vec<u8>,
+}
+
+impl Envelope {
+    // 0x89 不可能出现在 UTF-8 文本的开头，所以旧的纯文本消息不会被误认
+    pub const MAGIC: [u8; 4] = [0x89, b'P', b'C', b'M'];
+    pub const VERSION: u8 = 1;
+
+    pub const DEFLATE: u8 = 1 << 0;
+    pub const ENCRYPTED: u8 = 1 << 1;
+    /// Set by [`crate::signature::sign`] on envelopes in the chunks it
+    /// covers; the signature itself is kept in its own chunk.
+    pub const SIGNED: u8 = 1 << 2;
+    pub const ZSTD: u8 = 1 << 3;
+    pub const SHARE: u8 = 1 << 4;
+    pub const METADATA: u8 = 1 << 5;
+    /// Flags that mark a compressed body.
+    pub const COMPRESSED: u8 = Self::DEFLATE | Self::ZSTD;
+
+    pub fn new(content_type: &str, body: Vec<u8>) -> Envelope {
+        Envelope {
+            version: Self::VERSION,
+            flags: 0,
+            content_type: content_type.to_string(),
+            filename: None,
+            share: None,
+            metadata: Metadata::default(),
+            body,
+        }
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.flags & Self::COMPRESSED != 0
+    }
+
+    pub fn compression(&self) -> Result<Compression> {
+        match self.flags & Self::COMPRESSED {
+            0 => Ok(Compression::None),
+            Self::DEFLATE => Ok(Compression::Deflate),
+            Self::ZSTD => Ok(Compression::Zstd),
+            _ => Err(Error::from("Envelope has conflicting compression flags")),
+        }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & Self::ENCRYPTED != 0
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.flags & Self::SIGNED != 0
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = self.header_bytes()?;
+        let length = u32::try_from(self.body.len()).map_err(|_| "Message body is too large")?;
+        bytes.extend_from_slice(&length.to_be_bytes());
+        bytes.extend_from_slice(&self.body);
+        Ok(bytes)
+    }
+
+    /// The serialized header up to the body length, authenticated when the
+    /// body is encrypted. [`Envelope::SIGNED`] is left out because signing
+    /// sets it after the message was sealed.
+    pub fn associated_data(&self) -> Result<Vec<u8>> {
+        let mut header = self.header_bytes()?;
+        header[Self::MAGIC.len() + 1] &= !Self::SIGNED;
+        Ok(header)
+    }
+
+    fn header_bytes(&self) -> Result<Vec<u8>> {
+        let filename = self.filename.as_deref().unwrap_or("");
+        let mut bytes = Vec::with_capacity(16 + self.content_type.len() + filename.len());
+        bytes.extend_from_slice(&Self::MAGIC);
+        bytes.push(self.version);
+        let mut flags = self.flags & !(Self::SHARE | Self::METADATA);
+        if self.share.is_some() {
+            flags |= Self::SHARE;
+        }
+        if !self.metadata.is_empty() {
+            flags |= Self::METADATA;
+        }
+        bytes.push(flags);
+        push_short_str(&mut bytes, &self.content_type, "Content type")?;
+        push_short_str(&mut bytes, filename, "Filename")?;
+        if let Some(share) = &self.share {
+            bytes.extend_from_slice(&share.set_id);
+            bytes.extend_from_slice(&[share.index, share.threshold, share.count]);
+        }
+        if !self.metadata.is_empty() {
+            bytes.extend_from_slice(&self.metadata.created.unwrap_or(0).to_be_bytes());
+            bytes.extend_from_slice(&self.metadata.expires.unwrap_or(0).to_be_bytes());
+            push_short_str(&mut bytes, self.metadata.label.as_deref().unwrap_or(""), "Label")?;
+        }
+        Ok(bytes)
+    }
+
+    /// Parses `bytes`, returning `None` if they don't start with the
+    /// envelope magic, i.e. were written before envelopes existed.
+    pub fn detect(bytes: &[u8]) -> Result<Option<Envelope>> {
+        if !bytes.starts_with(&Self::MAGIC) {
+            return Ok(None);
+        }
+        let mut reader = Reader { bytes: &bytes[Self::MAGIC.len()..] };
+        let version = reader.take(1)?[0];
+        if version > Self::VERSION {
+            return Err(Error::from(format!("Envelope version {} is not supported", version)));
+        }
+        let flags = reader.take(1)?[0];
+        let content_type = reader.short_str()?;
+        let filename = Some(reader.short_str()?).filter(|f| !f.is_empty());
+        let share = if flags & Self::SHARE != 0 {
+            let info = reader.take(ShareInfo::SIZE)?;
+            Some(ShareInfo {
+                set_id: info[..8].try_into()?,
+                index: info[8],
+                threshold: info[9],
+                count: info[10],
+            })
+        } else {
+            None
+        };
+        let mut metadata = Metadata::default();
+        if flags & Self::METADATA != 0 {
+            let time = |bytes: &[u8]| -> Result<Option<u64>> {
+                Ok(Some(u64::from_be_bytes(bytes.try_into()?)).filter(|&t| t != 0))
+            };
+            metadata.created = time(reader.take(8)?)?;
+            metadata.expires = time(reader.take(8)?)?;
+            metadata.label = Some(reader.short_str()?).filter(|l| !l.is_empty());
+        }
+        let length = u32::from_be_bytes(reader.take(4)?.try_into()?) as usize;
+        let body = reader.take(length)?.to_vec();
+        if !reader.bytes.is_empty() {
+            return Err(Error::from("Unexpected data after the message envelope"));
+        }
+        Ok(Some(Envelope {
+            version,
+            flags,
+            content_type,
+            filename,
+            share,
+            metadata,
+            body,
+        }))
+    }
+}
+
+fn push_short_str(bytes: &mut Vec<u8>, value: &str, what: &str) -> Result<()> {
+    let length = u8::try_from(value.len()).map_err(|_| Error::from(format!("{} is too long", what)))?;
+    bytes.push(length);
+    bytes.extend_from_slice(value.as_bytes());
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < n {
+            return Err(Error::from("Message envelope is truncated"));
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn short_str(&mut self) -> Result<String> {
+        let length = self.take(1)?[0] as usize;
+        Ok(String::from_utf8(self.take(length)?.to_vec())?)
+    }
+}
+
+/// How to compress a message body before it is encrypted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Deflate,
+    Zstd,
+}
+
+impl Compression {
+    fn flag(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Deflate => Envelope::DEFLATE,
+            Compression::Zstd => Envelope::ZSTD,
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Deflate => deflate(data, 9),
+            Compression::Zstd => Ok(zstd::encode_all(data, 19)?),
+        }
+    }
+
+    // 限制解压后的大小，防止压缩炸弹
+    fn decompress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Deflate => inflate(data, MAX_DECOMPRESSED_SIZE),
+            Compression::Zstd => {
+                let mut out = Vec::new();
+                zstd::Decoder::new(data)?
+                    .take(MAX_DECOMPRESSED_SIZE as u64 + 1)
+                    .read_to_end(&mut out)?;
+                if out.len() > MAX_DECOMPRESSED_SIZE {
+                    return Err(Error::from("Decompressed data exceeds the size limit"));
+                }
+                Ok(out)
+            }
+        }
+    }
+}
+
+/// How to encrypt a message body.
+pub enum Encryption {
+    Passphrase(String),
+    Recipients(Vec<Recipient>),
+}
+
+/// How to decrypt a message body.
+pub enum Decryption {
+    Passphrase(String),
+    Identity(Identity),
+}
+
+impl Decryption {
+    fn open(&self, sealed: &[u8], associated_data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Decryption::Passphrase(passphrase) => encrypt::open(passphrase, sealed, associated_data),
+            Decryption::Identity(identity) => recipient::open(identity, sealed, associated_data),
+        }
+    }
+}
+
+/// A decoded message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub content_type: String,
+    pub filename: Option<String>,
+    pub metadata: Metadata,
+    pub data: Vec<u8>,
+}
+
+impl Message {
+    pub fn text(text: &str) -> Message {
+        Message {
+            content_type: TEXT.to_string(),
+            filename: None,
+            metadata: Metadata::default(),
+            data: text.as_bytes().to_vec(),
+        }
+    }
+
+    /// A file's contents, with its name and a content type guessed from it.
+    pub fn file(filename: &str, data: Vec<u8>) -> Message {
+        Message {
+            content_type: content_type_for(filename).to_string(),
+            filename: Some(filename.to_string()),
+            metadata: Metadata::default(),
+            data,
+        }
+    }
+
+    pub fn is_text(&self) -> bool {
+        (self.content_type.starts_with("text/") || self.content_type == "application/json")
+            && std::str::from_utf8(&self.data).is_ok()
+    }
+
+    /// Compresses and encrypts the message if asked to and wraps it in an
+    /// [`Envelope`]. Compression is skipped when it doesn't save space.
+    pub fn seal(&self, compression: Compression, encryption: Option<&Encryption>) -> Result<Vec<u8>> {
+        let mut envelope = Envelope::new(&self.content_type, self.data.clone());
+        envelope.filename = self.filename.clone();
+        envelope.metadata = self.metadata.clone();
+        if compression != Compression::None {
+            let compressed = compression.compress(&self.data)?;
+            if compressed.len() < self.data.len() {
+                envelope.body = compressed;
+                envelope.flags |= compression.flag();
+            }
+        }
+        if let Some(encryption) = encryption {
+            // 头部在加密前定下来，作为关联数据一起认证
+            envelope.flags |= Envelope::ENCRYPTED;
+            let header = envelope.associated_data()?;
+            envelope.body = match encryption {
+                Encryption::Passphrase(passphrase) => encrypt::seal(passphrase, &envelope.body, &header)?,
+                Encryption::Recipients(recipients) => recipient::seal(recipients, &envelope.body, &header)?,
+            };
+        }
+        envelope.to_bytes()
+    }
+
+    /// Reads a message written by [`Message::seal`], or the raw payload of a
+    /// message written before envelopes existed.
+    pub fn open(bytes: &[u8], decryption: Option<&Decryption>) -> Result<Message> {
+        let envelope = match Envelope::detect(bytes)? {
+            Some(envelope) => envelope,
+            None => {
+                let data = match decryption {
+                    Some(decryption) => decryption.open(bytes, &[])?,
+                    None => bytes.to_vec(),
+                };
+                let content_type = if std::str::from_utf8(&data).is_ok() { TEXT } else { BINARY };
+                return Ok(Message {
+                    content_type: content_type.to_string(),
+                    filename: None,
+                    metadata: Metadata::default(),
+                    data,
+                });
+            }
+        };
+
+        if let Some(share) = envelope.share {
+            return Err(Error::from(format!(
+                "This is share {} of {}, join at least {} shares to read the message",
+                share.index, share.count, share.threshold
+            )));
+        }
+        let compression = envelope.compression()?;
+        let body = match (envelope.is_encrypted(), decryption) {
+            (true, Some(decryption)) => decryption.open(&envelope.body, &envelope.associated_data()?)?,
+            (true, None) => return Err(Error::from("Message is encrypted, a passphrase or identity is required")),
+            (false, _) => envelope.body,
+        };
+        Ok(Message {
+            content_type: envelope.content_type,
+            filename: envelope.filename,
+            metadata: envelope.metadata,
+            data: compression.decompress(&body)?,
+        })
+    }
+}
+
+/// Envelopes stored in the chunks of `png`, one per chunk type, read
+/// without decrypting them. Fragmented and error corrected payloads are
+/// reassembled first; chunks holding something else are skipped.
+pub fn list(png: &Png) -> Vec<(ChunkType, Envelope)> {
+    let mut types: Vec<&ChunkType> = Vec::new();
+    for chunk in png.chunks() {
+        if !types.contains(&chunk.chunk_type()) {
+            types.push(chunk.chunk_type());
+        }
+    }
+    types
+        .into_iter()
+        .filter_map(|chunk_type| {
+            let payload = fragment::extract(png, &chunk_type.to_string()).ok()?;
+            let payload = match ecc::detect(&payload) {
+                Ok(Some(corrected)) => corrected.data,
+                Ok(None) => payload,
+                Err(_) => return None,
+            };
+            let envelope = Envelope::detect(&payload).ok()??;
+            Some((chunk_type.clone(), envelope))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_envelope_round_trip() {
+        let mut envelope = Envelope::new("image/png", vec![1, 2, 3]);
+        envelope.filename = Some("dice.png".to_string());
+        envelope.flags = Envelope::ENCRYPTED;
+        let bytes = envelope.to_bytes().unwrap();
+
+        assert!(bytes.starts_with(&Envelope::MAGIC));
+        let parsed = Envelope::detect(&bytes).unwrap().unwrap();
+        assert_eq!(parsed, envelope);
+        assert!(parsed.is_encrypted());
+        assert!(!parsed.is_compressed());
+
+        envelope.share = Some(ShareInfo {
+            set_id: [7; 8],
+            index: 2,
+            threshold: 2,
+            count: 3,
+        });
+        let parsed = Envelope::detect(&envelope.to_bytes().unwrap()).unwrap().unwrap();
+        assert_eq!(parsed.flags, Envelope::ENCRYPTED | Envelope::SHARE);
+        assert_eq!(parsed.share, envelope.share);
+        assert!(Message::open(&envelope.to_bytes().unwrap(), None).is_err());
+    }
+
+    #[test]
+    fn test_envelope_rejects_bad_data() {
+        let bytes = Envelope::new(TEXT, b"hello".to_vec()).to_bytes().unwrap();
+        assert!(Envelope::detect(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert!(Envelope::detect(&longer).is_err());
+
+        let mut newer = bytes.clone();
+        newer[4] = Envelope::VERSION + 1;
+        assert!(Envelope::detect(&newer).is_err());
+    }
+
+    #[test]
+    fn test_compressed_message() {
+        let log = "{\"level\":\"info\",\"msg\":\"request served\"}\n".repeat(50);
+        let message = Message::text(&log);
+        let decryption = Decryption::Passphrase("hunter2".to_string());
+        let encryption = Encryption::Passphrase("hunter2".to_string());
+
+        for (compression, flag) in [(Compression::Deflate, Envelope::DEFLATE), (Compression::Zstd, Envelope::ZSTD)] {
+            let sealed = message.seal(compression, None).unwrap();
+            assert!(sealed.len() < log.len() / 5);
+            assert_eq!(Envelope::detect(&sealed).unwrap().unwrap().flags, flag);
+            assert_eq!(Message::open(&sealed, None).unwrap(), message);
+
+            let sealed = message.seal(compression, Some(&encryption)).unwrap();
+            assert_eq!(Message::open(&sealed, Some(&decryption)).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn test_incompressible_message_is_stored() {
+        let message = Message::text("short");
+        let sealed = message.seal(Compression::Zstd, None).unwrap();
+        assert!(!Envelope::detect(&sealed).unwrap().unwrap().is_compressed());
+        assert_eq!(Message::open(&sealed, None).unwrap(), message);
+    }
+
+    #[test]
+    fn test_decompression_size_guard() {
+        let bomb = zstd::encode_all(&vec![0u8; MAX_DECOMPRESSED_SIZE + 1][..], 3).unwrap();
+        let mut envelope = Envelope::new(TEXT, bomb);
+        envelope.flags = Envelope::ZSTD;
+        assert!(Message::open(&envelope.to_bytes().unwrap(), None).is_err());
+
+        envelope.flags = Envelope::ZSTD | Envelope::DEFLATE;
+        assert!(Message::open(&envelope.to_bytes().unwrap(), None).is_err());
+    }
+
+    #[test]
+    fn test_binary_file_message() {
+        let data: Vec<u8> = (0..=255).collect();
+        let message = Message::file("key.BIN", data.clone());
+        assert_eq!(message.content_type, BINARY);
+        assert!(!message.is_text());
+
+        let opened = Message::open(&message.seal(Compression::None, None).unwrap(), None).unwrap();
+        assert_eq!(opened.filename.as_deref(), Some("key.BIN"));
+        assert_eq!(opened.data, data);
+        assert_eq!(content_type_for("Report.PDF"), "application/pdf");
+        assert_eq!(content_type_for("README"), BINARY);
+        assert!(Message::file("notes.txt", b"hi".to_vec()).is_text());
+    }
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let mut message = Message::text("self destructs");
+        message.metadata = Metadata {
+            created: Some(1_700_000_000),
+            expires: Some(1_700_086_400),
+            label: Some("alice".to_string()),
+        };
+        let encryption = Encryption::Passphrase("hunter2".to_string());
+        let sealed = message.seal(Compression::None, Some(&encryption)).unwrap();
+
+        let envelope = Envelope::detect(&sealed).unwrap().unwrap();
+        assert_eq!(envelope.flags, Envelope::ENCRYPTED | Envelope::METADATA);
+        assert_eq!(envelope.metadata, message.metadata);
+        assert!(envelope.metadata.is_expired());
+        assert_eq!(
+            envelope.metadata.to_string(),
+            "label: alice, created: 2023-11-14 22:13:20 UTC, expired: 2023-11-15 22:13:20 UTC"
+        );
+        let decryption = Decryption::Passphrase("hunter2".to_string());
+        assert_eq!(Message::open(&sealed, Some(&decryption)).unwrap(), message);
+
+        let plain = Envelope::new(TEXT, Vec::new());
+        assert_eq!(Envelope::detect(&plain.to_bytes().unwrap()).unwrap().unwrap().flags, 0);
+    }
+
+    #[test]
+    fn test_edited_header_fails_decryption() {
+        let mut message = Message::text("self destructs");
+        message.metadata.expires = Some(1_700_086_400);
+        let identity = Identity::generate();
+        let encryptions = [
+            (Encryption::Passphrase("hunter2".to_string()), Decryption::Passphrase("hunter2".to_string())),
+            (Encryption::Recipients(vec![identity.recipient()]), Decryption::Identity(identity)),
+        ];
+        for (encryption, decryption) in &encryptions {
+            let sealed = message.seal(Compression::None, Some(encryption)).unwrap();
+            let mut envelope = Envelope::detect(&sealed).unwrap().unwrap();
+            envelope.metadata.expires = None;
+            let edited = envelope.to_bytes().unwrap();
+            assert!(Message::open(&edited, Some(decryption)).is_err());
+
+            let mut envelope = Envelope::detect(&sealed).unwrap().unwrap();
+            envelope.filename = Some("other.txt".to_string());
+            assert!(Message::open(&envelope.to_bytes().unwrap(), Some(decryption)).is_err());
+            assert_eq!(Message::open(&sealed, Some(decryption)).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn test_list_messages() {
+        let chunk = |t: &str, data: Vec<u8>| Chunk::new(ChunkType::from_str(t).unwrap(), data);
+        let mut message = Message::file("notes.txt", b"hello".to_vec());
+        message.metadata.label = Some("bob".to_string());
+        let sealed = message.seal(Compression::None, None).unwrap();
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", Vec::new()),
+            chunk("tEXt", b"Comment\0plain".to_vec()),
+            chunk("IDAT", Vec::new()),
+            chunk("IEND", Vec::new()),
+        ]);
+        png.append_chunk(chunk("ruSt", sealed.clone()));
+        png.append_chunk(chunk("ecCo", ecc::encode(&sealed, 8).unwrap()));
+        fragment::embed(&mut png, &ChunkType::from_str("frAg").unwrap(), &sealed, 10).unwrap();
+
+        let listed = list(&png);
+        let types: Vec<String> = listed.iter().map(|(t, _)| t.to_string()).collect();
+        assert_eq!(types, vec!["ruSt", "ecCo", "frAg"]);
+        assert!(listed.iter().all(|(_, e)| e.filename.as_deref() == Some("notes.txt")));
+        assert_eq!(listed[0].1.metadata.label.as_deref(), Some("bob"));
+    }
+
+    #[test]
+    fn test_legacy_payload() {
+        assert_eq!(Envelope::detect(b"Hello, world!").unwrap(), None);
+        let message = Message::open(b"Hello, world!", None).unwrap();
+        assert_eq!(message, Message::text("Hello, world!"));
+
+        let sealed = encrypt::seal("hunter2", b"old secret", b"").unwrap();
+        let decryption = Decryption::Passphrase("hunter2".to_string());
+        assert_eq!(Message::open(&sealed, Some(&decryption)).unwrap().data, b"old secret");
+    }
+
+    #[test]
+    fn test_message_seal_and_open() {
+        let message = Message::text("Hello, world!");
+        let plain = message.seal(Compression::None, None).unwrap();
+        assert_eq!(Message::open(&plain, None).unwrap(), message);
+
+        let identity = Identity::generate();
+        let encryption = Encryption::Recipients(vec![identity.recipient()]);
+        let sealed = message.seal(Compression::None, Some(&encryption)).unwrap();
+        assert!(Envelope::detect(&sealed).unwrap().unwrap().is_encrypted());
+        assert!(Message::open(&sealed, None).is_err());
+        let decryption = Decryption::Identity(identity);
+        assert_eq!(Message::open(&sealed, Some(&decryption)).unwrap(), message);
+    }
+}
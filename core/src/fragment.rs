@@ -0,0 +1,318 @@
+use std::collections::BTreeMap;
+
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::{Error, Result};
+
+// 与 envelope 的 magic 一样以 0x89 开头，不会和旧的文本消息混淆
+pub const MAGIC: [u8; 4] = [0x89, b'P', b'C', b'F'];
+/// Size of the header in front of every fragment.
+pub const HEADER_SIZE: usize = 4 + 8 + 4 + 4 + 8 + 32;
+/// Fragment size used when a payload doesn't fit in a single chunk.
+pub const DEFAULT_FRAGMENT_SIZE: usize = 1024 * 1024;
+/// Largest data length the PNG specification allows for a chunk.
+pub const MAX_CHUNK_DATA: usize = (1 << 31) - 1;
+// 缺少的片段超过这个数量时只报告数量
+const MAX_LISTED_MISSING: usize = 64;
+
+/// Header stored in front of every fragment.
+///
+/// Layout: `magic | message id (8) | index (u32) | count (u32) | total length
+/// (u64) | SHA-256 of the whole payload`. Every fragment repeats the totals so
+/// any one of them is enough to tell what is missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Header {
+    id: [u8; 8],
+    index: u32,
+    count: u32,
+    total: u64,
+    digest: [u8; 32],
+}
+
+impl Header {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&self.id);
+        bytes.extend_from_slice(&self.index.to_be_bytes());
+        bytes.extend_from_slice(&self.count.to_be_bytes());
+        bytes.extend_from_slice(&self.total.to_be_bytes());
+        bytes.extend_from_slice(&self.digest);
+        bytes
+    }
+
+    fn parse(data: &[u8]) -> Result<(Header, &[u8])> {
+        if !is_fragment(data) || data.len() < HEADER_SIZE {
+            return Err(Error::from("Not a message fragment"));
+        }
+        let header = Header {
+            id: data[4..12].try_into()?,
+            index: u32::from_be_bytes(data[12..16].try_into()?),
+            count: u32::from_be_bytes(data[16..20].try_into()?),
+            total: u64::from_be_bytes(data[20..28].try_into()?),
+            digest: data[28..HEADER_SIZE].try_into()?,
+        };
+        if header.index >= header.count {
+            return Err(Error::from("Fragment index out of range"));
+        }
+        Ok((header, &data[HEADER_SIZE..]))
+    }
+}
+
+/// Whether `data` starts like a fragment written by [`split`].
+pub fn is_fragment(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Cuts `payload` into fragments of at most `fragment_size` payload bytes,
+/// each prefixed with a header.
+pub fn split(payload: &[u8], fragment_size: usize) -> Result<Vec<Vec<u8>>> {
+    if fragment_size == 0 || fragment_size > MAX_CHUNK_DATA - HEADER_SIZE {
+        return Err(Error::from("Invalid fragment size"));
+    }
+    let mut parts: Vec<&[u8]> = payload.chunks(fragment_size).collect();
+    if parts.is_empty() {
+        parts.push(&[]);
+    }
+    frame(payload, &parts)
+}
+
+/// Cuts `payload` into exactly `count` fragments of nearly equal size.
+pub fn split_into(payload: &[u8], count: usize) -> Result<Vec<Vec<u8>>> {
+    if count == 0 {
+        return Err(Error::from("At least one fragment is required"));
+    }
+    let bounds: Vec<usize> = (0..=count).map(|i| i * payload.len() / count).collect();
+    let parts: Vec<&[u8]> = bounds.windows(2).map(|w| &payload[w[0]..w[1]]).collect();
+    if parts.iter().any(|part| part.len() > MAX_CHUNK_DATA - HEADER_SIZE) {
+        return Err(Error::from("Fragments would exceed the chunk size limit"));
+    }
+    frame(payload, &parts)
+}
+
+fn frame(payload: &[u8], parts: &[&[u8]]) -> Result<Vec<Vec<u8>>> {
+    let mut id = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut id);
+    let mut header = Header {
+        id,
+        index: 0,
+        count: u32::try_from(parts.len()).map_err(|_| "Too many fragments")?,
+        total: payload.len() as u64,
+        digest: Sha256::digest(payload).into(),
+    };
+
+    let mut fragments = Vec::with_capacity(parts.len());
+    for (index, part) in parts.iter().enumerate() {
+        header.index = index as u32;
+        let mut fragment = header.to_bytes();
+        fragment.extend_from_slice(part);
+        fragments.push(fragment);
+    }
+    Ok(fragments)
+}
+
+/// Reassembles fragments written by [`split`], in any order. Fails with the
+/// list of missing indices if some are absent.
+pub fn join<'a, I>(fragments: I) -> Result<Vec<u8>>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let mut first: Option<Header> = None;
+    // count 来自未经验证的数据，不能按它预先分配
+    let mut parts: BTreeMap<u32, &[u8]> = BTreeMap::new();
+    for data in fragments {
+        let (header, part) = Header::parse(data)?;
+        let expected = first.get_or_insert_with(|| header.clone());
+        if (header.id, header.count, header.total, header.digest)
+            != (expected.id, expected.count, expected.total, expected.digest)
+        {
+            return Err(Error::from("Fragments belong to more than one message"));
+        }
+        match parts.get(&header.index) {
+            Some(&existing) if existing != part => {
+                return Err(Error::from(format!("Conflicting copies of fragment {}", header.index)));
+            }
+            _ => {
+                parts.insert(header.index, part);
+            }
+        }
+    }
+    let header = first.ok_or("No fragments found")?;
+
+    let missing_count = header.count as usize - parts.len();
+    if missing_count > MAX_LISTED_MISSING {
+        return Err(Error::from(format!("Missing {} of {} fragments", missing_count, header.count)));
+    }
+    // 缺得不多时 count 不超过实际提供的片段数加上这个上限，可以逐个列出
+    let missing: Vec<String> = (0..header.count)
+        .filter(|index| !parts.contains_key(index))
+        .map(|index| index.to_string())
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::from(format!(
+            "Missing fragments {} of {}",
+            missing.join(", "),
+            header.count
+        )));
+    }
+
+    let payload: Vec<u8> = parts.into_values().flatten().copied().collect();
+    if payload.len() as u64 != header.total || Sha256::digest(&payload)[..] != header.digest {
+        return Err(Error::from("Reassembled payload does not match its digest"));
+    }
+    Ok(payload)
+}
+
+/// Stores `payload` in chunks of `chunk_type`, fragmented into pieces of
+/// `fragment_size` bytes. Returns the number of chunks written.
+pub fn embed(png: &mut Png, chunk_type: &ChunkType, payload: &[u8], fragment_size: usize) -> Result<usize> {
+    let fragments = split(payload, fragment_size)?;
+    let count = fragments.len();
+    for fragment in fragments {
+        png.append_chunk(Chunk::new(chunk_type.clone(), fragment));
+    }
+    Ok(count)
+}
+
+/// Reads the payload stored in chunks of `chunk_type`, reassembling it if it
+/// was fragmented.
+pub fn extract(png: &Png, chunk_type: &str) -> Result<Vec<u8>> {
+    let chunks = chunks_of_type(png, chunk_type);
+    match chunks.first() {
+        None => Err(Error::from("Chunk not found")),
+        Some(chunk) if !is_fragment(chunk.data()) => Ok(chunk.data().to_vec()),
+        Some(_) => join(chunks.iter().map(|c| c.data())),
+    }
+}
+
+/// Shards `payload` over several images, one fragment per image, so no
+/// single file holds the whole message. The fragment headers carry the set
+/// id, index and count.
+pub fn embed_across(pngs: &mut [Png], chunk_type: &ChunkType, payload: &[u8]) -> Result<()> {
+    let fragments = split_into(payload, pngs.len())?;
+    for (png, fragment) in pngs.iter_mut().zip(fragments) {
+        png.append_chunk(Chunk::new(chunk_type.clone(), fragment));
+    }
+    Ok(())
+}
+
+/// Rebuilds a payload written by [`embed_across`] from the images, in any
+/// order.
+pub fn extract_across(pngs: &[Png], chunk_type: &str) -> Result<Vec<u8>> {
+    let chunks: Vec<&Chunk> = pngs.iter().flat_map(|png| chunks_of_type(png, chunk_type)).collect();
+    if chunks.is_empty() {
+        return Err(Error::from("Chunk not found"));
+    }
+    join(chunks.iter().map(|c| c.data()))
+}
+
+fn chunks_of_type<'a>(png: &'a Png, chunk_type: &str) -> Vec<&'a Chunk> {
+    png.chunks()
+        .iter()
+        .filter(|c| c.chunk_type().to_string() == chunk_type)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn payload() -> Vec<u8> {
+        (0..1000).map(|i| (i * 7 % 256) as u8).collect()
+    }
+
+    #[test]
+    fn test_split_and_join_out_of_order() {
+        let mut fragments = split(&payload(), 300).unwrap();
+        assert_eq!(fragments.len(), 4);
+        assert_eq!(fragments[3].len(), HEADER_SIZE + 100);
+
+        fragments.reverse();
+        fragments.push(fragments[1].clone());
+        assert_eq!(join(fragments.iter().map(|f| f.as_slice())).unwrap(), payload());
+    }
+
+    #[test]
+    fn test_join_reports_missing_fragments() {
+        let fragments = split(&payload(), 100).unwrap();
+        let partial = fragments.iter().enumerate().filter(|(i, _)| *i != 2 && *i != 7);
+        let error = join(partial.map(|(_, f)| f.as_slice())).unwrap_err();
+        assert_eq!(error.to_string(), "Missing fragments 2, 7 of 10");
+    }
+
+    #[test]
+    fn test_join_rejects_hostile_count() {
+        let header = Header {
+            id: [7; 8],
+            index: 0,
+            count: u32::MAX,
+            total: 1,
+            digest: [0; 32],
+        };
+        let mut fragment = header.to_bytes();
+        fragment.push(1);
+        let error = join([fragment.as_slice()]).unwrap_err();
+        assert_eq!(error.to_string(), "Missing 4294967294 of 4294967295 fragments");
+
+        let chunk = |t: &str, data: Vec<u8>| Chunk::new(ChunkType::from_str(t).unwrap(), data);
+        let png = Png::from_chunks(vec![chunk("IHDR", Vec::new()), chunk("ruSt", fragment), chunk("IEND", Vec::new())]);
+        assert!(extract(&png, "ruSt").is_err());
+    }
+
+    #[test]
+    fn test_join_rejects_mixed_or_corrupt_fragments() {
+        let first = split(&payload(), 500).unwrap();
+        let second = split(&payload(), 500).unwrap();
+        assert!(join([first[0].as_slice(), second[1].as_slice()]).is_err());
+
+        let mut corrupt = first.clone();
+        let last = corrupt[1].len() - 1;
+        corrupt[1][last] ^= 1;
+        assert!(join(corrupt.iter().map(|f| f.as_slice())).is_err());
+        assert!(split(&payload(), 0).is_err());
+    }
+
+    #[test]
+    fn test_split_into_exact_count() {
+        let fragments = split_into(b"ab", 3).unwrap();
+        assert_eq!(fragments.len(), 3);
+        assert_eq!(join(fragments.iter().rev().map(|f| f.as_slice())).unwrap(), b"ab");
+        assert!(split_into(b"ab", 0).is_err());
+    }
+
+    #[test]
+    fn test_embed_and_extract_across_images() {
+        let chunk = |t: &str| Chunk::new(ChunkType::from_str(t).unwrap(), Vec::new());
+        let image = || Png::from_chunks(vec![chunk("IHDR"), chunk("IDAT"), chunk("IEND")]);
+        let mut pngs = vec![image(), image(), image()];
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+
+        embed_across(&mut pngs, &chunk_type, &payload()).unwrap();
+        assert!(pngs.iter().all(|png| png.chunks().len() == 4));
+        pngs.swap(0, 2);
+        assert_eq!(extract_across(&pngs, "ruSt").unwrap(), payload());
+
+        let error = extract_across(&pngs[..2], "ruSt").unwrap_err();
+        assert_eq!(error.to_string(), "Missing fragments 0 of 3");
+    }
+
+    #[test]
+    fn test_embed_and_extract() {
+        let chunk = |t: &str| Chunk::new(ChunkType::from_str(t).unwrap(), Vec::new());
+        let mut png = Png::from_chunks(vec![chunk("IHDR"), chunk("IDAT"), chunk("IEND")]);
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+
+        assert_eq!(embed(&mut png, &chunk_type, &payload(), 256).unwrap(), 4);
+        assert_eq!(png.chunks().len(), 7);
+        assert_eq!(extract(&png, "ruSt").unwrap(), payload());
+
+        png.remove_first_chunk("ruSt").unwrap();
+        assert!(extract(&png, "ruSt").is_err());
+        assert!(extract(&png, "abCd").is_err());
+    }
+}
@@ -0,0 +1,73 @@
+// GF(2^8) 运算，秘密共享和纠错码共用
+/// Reduction polynomial x^8 + x^4 + x^3 + x^2 + 1.
+const POLYNOMIAL: u16 = 0x11d;
+
+// 指数表重复一遍，乘法时不用取模
+const EXP: [u8; 512] = {
+    let mut table = [0u8; 512];
+    let mut value: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        table[i] = value as u8;
+        table[i + 255] = value as u8;
+        value <<= 1;
+        if value & 0x100 != 0 {
+            value ^= POLYNOMIAL;
+        }
+        i += 1;
+    }
+    table
+};
+
+const LOG: [u8; 256] = {
+    let mut table = [0u8; 256];
+    let mut i = 0;
+    while i < 255 {
+        table[EXP[i] as usize] = i as u8;
+        i += 1;
+    }
+    table
+};
+
+pub(crate) fn mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    EXP[LOG[a as usize] as usize + LOG[b as usize] as usize]
+}
+
+/// Panics when dividing by zero, like integer division.
+pub(crate) fn div(a: u8, b: u8) -> u8 {
+    assert!(b != 0, "division by zero in GF(256)");
+    if a == 0 {
+        return 0;
+    }
+    EXP[LOG[a as usize] as usize + 255 - LOG[b as usize] as usize]
+}
+
+/// The generator raised to `power`, which may be negative.
+pub(crate) fn exp(power: isize) -> u8 {
+    EXP[power.rem_euclid(255) as usize]
+}
+
+/// Evaluates a polynomial given highest degree coefficient first.
+pub(crate) fn eval(poly: &[u8], x: u8) -> u8 {
+    poly.iter().fold(0, |acc, &c| mul(acc, x) ^ c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_arithmetic() {
+        for a in 1..=255u8 {
+            assert_eq!(div(mul(a, 7), 7), a);
+            assert_eq!(mul(a, div(1, a)), 1);
+        }
+        assert_eq!(mul(0, 9), 0);
+        assert_eq!(mul(0x80, 2), 0x1d);
+        // 3x^2 + 2x + 1 在 x = 2 处
+        assert_eq!(eval(&[3, 2, 1], 2), mul(3, 4) ^ mul(2, 2) ^ 1);
+    }
+}
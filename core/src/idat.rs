@@ -0,0 +1,461 @@
+use std::io::{Read, Write};
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Decompress, FlushDecompress, Status};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::Ihdr;
+use crate::png::Png;
+use crate::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+    None = 0,
+    Sub = 1,
+    Up = 2,
+    Average = 3,
+    Paeth = 4,
+}
+
+impl FilterType {
+    pub const ALL: [FilterType; 5] = [
+        FilterType::None,
+        FilterType::Sub,
+        FilterType::Up,
+        FilterType::Average,
+        FilterType::Paeth,
+    ];
+}
+
+impl TryFrom<u8> for FilterType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(FilterType::None),
+            1 => Ok(FilterType::Sub),
+            2 => Ok(FilterType::Up),
+            3 => Ok(FilterType::Average),
+            4 => Ok(FilterType::Paeth),
+            _ => Err(Error::from("Invalid filter type")),
+        }
+    }
+}
+
+/// How a filter type is chosen for each scanline when encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterStrategy {
+    Fixed(FilterType),
+    /// Per-row choice using the minimum sum of absolute differences heuristic.
+    Adaptive,
+}
+
+impl FilterStrategy {
+    pub const ALL: [FilterStrategy; 6] = [
+        FilterStrategy::Fixed(FilterType::None),
+        FilterStrategy::Fixed(FilterType::Sub),
+        FilterStrategy::Fixed(FilterType::Up),
+        FilterStrategy::Fixed(FilterType::Average),
+        FilterStrategy::Fixed(FilterType::Paeth),
+        FilterStrategy::Adaptive,
+    ];
+}
+
+// Adam7 每一遍的 (x 起点, y 起点, x 步长, y 步长)
+const ADAM7_PASSES: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Unfiltered, non-interlaced scanlines of a PNG image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Raster {
+    ihdr: Ihdr,
+    data: Vec<u8>,
+}
+
+impl Raster {
+    pub fn new(mut ihdr: Ihdr, data: Vec<u8>) -> Result<Raster> {
+        ihdr.interlaced = false;
+        if data.len() != ihdr.stride() * ihdr.height as usize {
+            return Err(Error::from("Raster data does not match image dimensions"));
+        }
+        Ok(Raster { ihdr, data })
+    }
+
+    /// Inflates and unfilters the IDAT stream of `png`. Interlaced images are
+    /// returned de-interlaced.
+    pub fn decode(png: &Png) -> Result<Raster> {
+        Raster::decode_stream(png.ihdr()?, &png.image_data())
+    }
+
+    /// Inflates and unfilters a zlib stream laid out as `ihdr` describes,
+    /// such as the data of one APNG frame.
+    pub fn decode_stream(ihdr: Ihdr, zlib_data: &[u8]) -> Result<Raster> {
+        let expected = if ihdr.interlaced {
+            pass_sizes(&ihdr)
+                .iter()
+                .filter(|(w, h)| *w > 0 && *h > 0)
+                .map(|&(w, h)| (ihdr.stride_for(w) + 1) * h as usize)
+                .sum()
+        } else {
+            (ihdr.stride() + 1) * ihdr.height as usize
+        };
+        let filtered = inflate(zlib_data, expected)?;
+        if filtered.len() != expected {
+            return Err(Error::from("Image data has the wrong length"));
+        }
+
+        if !ihdr.interlaced {
+            let data = unfilter(&filtered, &ihdr, ihdr.width, ihdr.height)?;
+            return Raster::new(ihdr, data);
+        }
+
+        let mut raster = Raster::new(ihdr, vec![0; ihdr.stride() * ihdr.height as usize])?;
+        let bits = ihdr.bits_per_pixel();
+        let mut offset = 0;
+        for (&(x0, y0, dx, dy), (w, h)) in ADAM7_PASSES.iter().zip(pass_sizes(&ihdr)) {
+            if w == 0 || h == 0 {
+                continue;
+            }
+            let len = (ihdr.stride_for(w) + 1) * h as usize;
+            let pass = unfilter(&filtered[offset..offset + len], &ihdr, w, h)?;
+            offset += len;
+
+            let pass_stride = ihdr.stride_for(w);
+            for py in 0..h {
+                let row = &pass[py as usize * pass_stride..][..pass_stride];
+                let y = y0 + py * dy;
+                for px in 0..w {
+                    let value = read_bits(row, px as usize * bits, bits);
+                    raster.write_pixel_bits(x0 + px * dx, y, value);
+                }
+            }
+        }
+        Ok(raster)
+    }
+
+    pub fn ihdr(&self) -> &Ihdr {
+        &self.ihdr
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    pub fn width(&self) -> u32 {
+        self.ihdr.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.ihdr.height
+    }
+
+    pub fn row(&self, y: u32) -> &[u8] {
+        let stride = self.ihdr.stride();
+        &self.data[y as usize * stride..][..stride]
+    }
+
+    /// Reads one sample (a channel of a pixel, or a palette index) at its
+    /// native bit depth.
+    pub fn sample(&self, x: u32, y: u32, channel: usize) -> u16 {
+        let depth = self.ihdr.bit_depth as usize;
+        let bit = (x as usize * self.ihdr.color_type.channels() + channel) * depth;
+        read_bits(self.row(y), bit, depth) as u16
+    }
+
+    pub fn set_sample(&mut self, x: u32, y: u32, channel: usize, value: u16) {
+        let depth = self.ihdr.bit_depth as usize;
+        let bit = (x as usize * self.ihdr.color_type.channels() + channel) * depth;
+        let stride = self.ihdr.stride();
+        let row = &mut self.data[y as usize * stride..][..stride];
+        write_bits(row, bit, depth, value as u64);
+    }
+
+    fn write_pixel_bits(&mut self, x: u32, y: u32, value: u64) {
+        let bits = self.ihdr.bits_per_pixel();
+        let stride = self.ihdr.stride();
+        let row = &mut self.data[y as usize * stride..][..stride];
+        write_bits(row, x as usize * bits, bits, value);
+    }
+
+    /// A still PNG holding just this raster.
+    pub fn to_png(&self) -> Result<Png> {
+        let idat = self.encode(FilterStrategy::Adaptive, 9)?;
+        Ok(Png::from_chunks(vec![
+            self.ihdr.to_chunk(),
+            Chunk::new(ChunkType::IDAT, idat),
+            Chunk::new(ChunkType::IEND, Vec::new()),
+        ]))
+    }
+
+    /// Filters every scanline and deflates the result into a zlib stream
+    /// suitable for IDAT.
+    pub fn encode(&self, strategy: FilterStrategy, level: u32) -> Result<Vec<u8>> {
+        deflate(&self.filter(strategy), level)
+    }
+
+    pub fn filter(&self, strategy: FilterStrategy) -> Vec<u8> {
+        let stride = self.ihdr.stride();
+        let bpp = self.ihdr.bytes_per_pixel();
+        let zero_row = vec![0; stride];
+        let mut out = Vec::with_capacity((stride + 1) * self.ihdr.height as usize);
+        let mut candidate = vec![0; stride];
+
+        for y in 0..self.ihdr.height {
+            let row = self.row(y);
+            let prior = if y == 0 { &zero_row[..] } else { self.row(y - 1) };
+            match strategy {
+                FilterStrategy::Fixed(filter_type) => {
+                    filter_row(filter_type, row, prior, bpp, &mut candidate);
+                    out.push(filter_type as u8);
+                    out.extend_from_slice(&candidate);
+                }
+                FilterStrategy::Adaptive => {
+                    let mut best: Option<(u64, FilterType, Vec<u8>)> = None;
+                    for filter_type in FilterType::ALL {
+                        filter_row(filter_type, row, prior, bpp, &mut candidate);
+                        let score = candidate.iter().map(|&b| (b as i8).unsigned_abs() as u64).sum();
+                        if best.as_ref().is_none_or(|(s, _, _)| score < *s) {
+                            best = Some((score, filter_type, candidate.clone()));
+                        }
+                    }
+                    let (_, filter_type, filtered) = best.unwrap();
+                    out.push(filter_type as u8);
+                    out.extend_from_slice(&filtered);
+                }
+            }
+        }
+        out
+    }
+}
+
+fn pass_sizes(ihdr: &Ihdr) -> Vec<(u32, u32)> {
+    ADAM7_PASSES
+        .iter()
+        .map(|&(x0, y0, dx, dy)| {
+            let w = ihdr.width.saturating_sub(x0).div_ceil(dx);
+            let h = ihdr.height.saturating_sub(y0).div_ceil(dy);
+            (w, h)
+        })
+        .collect()
+}
+
+fn unfilter(filtered: &[u8], ihdr: &Ihdr, width: u32, height: u32) -> Result<Vec<u8>> {
+    let stride = ihdr.stride_for(width);
+    let bpp = ihdr.bytes_per_pixel();
+    let mut out = vec![0u8; stride * height as usize];
+
+    for y in 0..height as usize {
+        let line = &filtered[y * (stride + 1)..][..stride + 1];
+        let filter_type = FilterType::try_from(line[0])?;
+        let (done, rest) = out.split_at_mut(y * stride);
+        let prior = if y == 0 { None } else { Some(&done[(y - 1) * stride..]) };
+        let row = &mut rest[..stride];
+        row.copy_from_slice(&line[1..]);
+
+        for i in 0..stride {
+            let a = if i >= bpp { row[i - bpp] } else { 0 };
+            let b = prior.map_or(0, |p| p[i]);
+            let c = if i >= bpp { prior.map_or(0, |p| p[i - bpp]) } else { 0 };
+            row[i] = match filter_type {
+                FilterType::None => row[i],
+                FilterType::Sub => row[i].wrapping_add(a),
+                FilterType::Up => row[i].wrapping_add(b),
+                FilterType::Average => row[i].wrapping_add(((a as u16 + b as u16) / 2) as u8),
+                FilterType::Paeth => row[i].wrapping_add(paeth(a, b, c)),
+            };
+        }
+    }
+    Ok(out)
+}
+
+fn filter_row(filter_type: FilterType, row: &[u8], prior: &[u8], bpp: usize, out: &mut [u8]) {
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = prior[i];
+        let c = if i >= bpp { prior[i - bpp] } else { 0 };
+        out[i] = match filter_type {
+            FilterType::None => row[i],
+            FilterType::Sub => row[i].wrapping_sub(a),
+            FilterType::Up => row[i].wrapping_sub(b),
+            FilterType::Average => row[i].wrapping_sub(((a as u16 + b as u16) / 2) as u8),
+            FilterType::Paeth => row[i].wrapping_sub(paeth(a, b, c)),
+        };
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// 按大端位序读取从 bit 开始的 len 位 (len <= 64)
+fn read_bits(row: &[u8], bit: usize, len: usize) -> u64 {
+    if bit.is_multiple_of(8) && len.is_multiple_of(8) {
+        return row[bit / 8..][..len / 8]
+            .iter()
+            .fold(0, |acc, &b| (acc << 8) | b as u64);
+    }
+    let shift = 8 - (bit % 8) - len;
+    ((row[bit / 8] >> shift) & ((1u16 << len) - 1) as u8) as u64
+}
+
+fn write_bits(row: &mut [u8], bit: usize, len: usize, value: u64) {
+    if bit.is_multiple_of(8) && len.is_multiple_of(8) {
+        let bytes = len / 8;
+        for (i, byte) in row[bit / 8..][..bytes].iter_mut().enumerate() {
+            *byte = (value >> (8 * (bytes - 1 - i))) as u8;
+        }
+        return;
+    }
+    let shift = 8 - (bit % 8) - len;
+    let mask = (((1u16 << len) - 1) as u8) << shift;
+    let byte = &mut row[bit / 8];
+    *byte = (*byte & !mask) | (((value as u8) << shift) & mask);
+}
+
+/// Inflates a zlib stream, refusing to produce more than `limit` bytes.
+pub fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    ZlibDecoder::new(data)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut out)?;
+    if out.len() > limit {
+        return Err(Error::from("Decompressed data exceeds the size limit"));
+    }
+    Ok(out)
+}
+
+/// Number of bytes at the start of `data` that make up one complete zlib
+/// stream. Anything after that is ignored by decoders.
+pub fn zlib_stream_len(data: &[u8]) -> Result<usize> {
+    let mut inflater = Decompress::new(true);
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let (consumed, produced) = (inflater.total_in(), inflater.total_out());
+        let status = inflater.decompress(&data[consumed as usize..], &mut buffer, FlushDecompress::None)?;
+        if status == Status::StreamEnd {
+            return Ok(inflater.total_in() as usize);
+        }
+        if inflater.total_in() == consumed && inflater.total_out() == produced {
+            return Err(Error::from("Truncated zlib stream"));
+        }
+    }
+}
+
+pub fn deflate(data: &[u8], level: u32) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level));
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::ColorType;
+    use std::str::FromStr;
+
+    fn testing_raster(color_type: ColorType, bit_depth: u8) -> Raster {
+        let ihdr = Ihdr {
+            width: 7,
+            height: 5,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        };
+        let data = (0..ihdr.stride() * 5).map(|i| (i * 37 % 251) as u8).collect();
+        Raster::new(ihdr, data).unwrap()
+    }
+
+    fn png_with(ihdr: &Ihdr, idat: Vec<u8>) -> Png {
+        let chunks = vec![
+            ihdr.to_chunk(),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), idat),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ];
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_filter_round_trip() {
+        let raster = testing_raster(ColorType::Rgb, 8);
+        for strategy in FilterStrategy::ALL {
+            let png = png_with(raster.ihdr(), raster.encode(strategy, 6).unwrap());
+            assert_eq!(Raster::decode(&png).unwrap(), raster, "{:?}", strategy);
+        }
+        assert_eq!(Raster::decode(&raster.to_png().unwrap()).unwrap(), raster);
+    }
+
+    #[test]
+    fn test_sub_byte_samples() {
+        let mut raster = testing_raster(ColorType::Grayscale, 2);
+        raster.set_sample(5, 3, 0, 2);
+        assert_eq!(raster.sample(5, 3, 0), 2);
+        raster.set_sample(5, 3, 0, 1);
+        assert_eq!(raster.sample(5, 3, 0), 1);
+        let png = png_with(raster.ihdr(), raster.encode(FilterStrategy::Adaptive, 6).unwrap());
+        assert_eq!(Raster::decode(&png).unwrap(), raster);
+    }
+
+    #[test]
+    fn test_decode_interlaced() {
+        let raster = testing_raster(ColorType::GrayscaleAlpha, 8);
+        let mut ihdr = *raster.ihdr();
+        ihdr.interlaced = true;
+
+        // 手工构造 Adam7 数据：每一遍都使用 None filter
+        let mut filtered = Vec::new();
+        for (&(x0, y0, dx, dy), (w, h)) in ADAM7_PASSES.iter().zip(pass_sizes(&ihdr)) {
+            if w == 0 || h == 0 {
+                continue;
+            }
+            for py in 0..h {
+                filtered.push(0);
+                for px in 0..w {
+                    for c in 0..2 {
+                        filtered.push(raster.sample(x0 + px * dx, y0 + py * dy, c) as u8);
+                    }
+                }
+            }
+        }
+        let png = png_with(&ihdr, deflate(&filtered, 6).unwrap());
+        assert_eq!(Raster::decode(&png).unwrap(), raster);
+    }
+
+    #[test]
+    fn test_zlib_stream_len() {
+        let mut data = deflate(&[7; 1000], 9).unwrap();
+        let len = data.len();
+        data.extend_from_slice(b"trailing");
+        assert_eq!(zlib_stream_len(&data).unwrap(), len);
+        assert!(zlib_stream_len(&data[..len - 1]).is_err());
+    }
+
+    #[test]
+    fn test_inflate_limit() {
+        let data = deflate(&[0; 100], 9).unwrap();
+        assert!(inflate(&data, 99).is_err());
+        assert_eq!(inflate(&data, 100).unwrap().len(), 100);
+    }
+}
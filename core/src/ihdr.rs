@@ -0,0 +1,180 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale = 0,
+    Rgb = 2,
+    Indexed = 3,
+    GrayscaleAlpha = 4,
+    Rgba = 6,
+}
+
+impl ColorType {
+    pub fn channels(&self) -> usize {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        matches!(self, ColorType::GrayscaleAlpha | ColorType::Rgba)
+    }
+
+    // PNG 规范 Table 11.1 中每种颜色类型允许的位深
+    pub fn allowed_bit_depths(&self) -> &'static [u8] {
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => &[8, 16],
+        }
+    }
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => Err(Error::from("Invalid color type")),
+        }
+    }
+}
+
+/// The decoded contents of an IHDR chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub interlaced: bool,
+}
+
+impl Ihdr {
+    pub const DATA_SIZE: usize = 13;
+
+    pub fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() * self.bit_depth as usize
+    }
+
+    // filter 以字节为单位，位深小于 8 时按 1 字节计算
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+
+    /// Number of bytes in one unfiltered scanline of `width` pixels.
+    pub fn stride_for(&self, width: u32) -> usize {
+        (width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride_for(self.width)
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = Vec::with_capacity(Ihdr::DATA_SIZE);
+        data.extend_from_slice(&self.width.to_be_bytes());
+        data.extend_from_slice(&self.height.to_be_bytes());
+        data.push(self.bit_depth);
+        data.push(self.color_type as u8);
+        // compression method 和 filter method 目前只定义了 0
+        data.push(0);
+        data.push(0);
+        data.push(self.interlaced as u8);
+        Chunk::new(ChunkType::IHDR, data)
+    }
+}
+
+impl TryFrom<&Chunk> for Ihdr {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        if *chunk.chunk_type() != ChunkType::IHDR {
+            return Err(Error::from("Not an IHDR chunk"));
+        }
+        let data = chunk.data();
+        if data.len() != Ihdr::DATA_SIZE {
+            return Err(Error::from("Invalid IHDR length"));
+        }
+
+        let width = u32::from_be_bytes(data[0..4].try_into()?);
+        let height = u32::from_be_bytes(data[4..8].try_into()?);
+        let bit_depth = data[8];
+        let color_type = ColorType::try_from(data[9])?;
+
+        if width == 0 || height == 0 {
+            return Err(Error::from("Image dimensions must be non-zero"));
+        }
+        if !color_type.allowed_bit_depths().contains(&bit_depth) {
+            return Err(Error::from("Invalid bit depth for color type"));
+        }
+        if data[10] != 0 || data[11] != 0 {
+            return Err(Error::from("Unsupported compression or filter method"));
+        }
+        let interlaced = match data[12] {
+            0 => false,
+            1 => true,
+            _ => return Err(Error::from("Invalid interlace method")),
+        };
+
+        Ok(Ihdr {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlaced,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_ihdr() -> Ihdr {
+        Ihdr {
+            width: 50,
+            height: 30,
+            bit_depth: 8,
+            color_type: ColorType::Rgba,
+            interlaced: false,
+        }
+    }
+
+    #[test]
+    fn test_ihdr_round_trip() {
+        let ihdr = testing_ihdr();
+        let chunk = ihdr.to_chunk();
+        assert_eq!(chunk.length(), 13);
+        assert_eq!(Ihdr::try_from(&chunk).unwrap(), ihdr);
+    }
+
+    #[test]
+    fn test_ihdr_stride() {
+        let mut ihdr = testing_ihdr();
+        assert_eq!(ihdr.stride(), 200);
+        assert_eq!(ihdr.bytes_per_pixel(), 4);
+
+        ihdr.color_type = ColorType::Indexed;
+        ihdr.bit_depth = 2;
+        assert_eq!(ihdr.stride(), 13);
+        assert_eq!(ihdr.bytes_per_pixel(), 1);
+    }
+
+    #[test]
+    fn test_ihdr_invalid_bit_depth() {
+        let mut ihdr = testing_ihdr();
+        ihdr.bit_depth = 4;
+        assert!(Ihdr::try_from(&ihdr.to_chunk()).is_err());
+    }
+}
@@ -0,0 +1,44 @@
+//! Chunk types named after a secret shared by sender and receiver, so every
+//! pair uses its own non-obvious type and neither has to type it.
+
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use crate::chunk_type::ChunkType;
+
+const INFO: &[u8] = b"png-chunk-msg chunk type";
+
+/// Derives an ancillary, private, safe-to-copy chunk type from `secret`.
+pub fn from_secret(secret: &[u8]) -> ChunkType {
+    let mut letters = [0u8; 4];
+    Hkdf::<Sha256>::new(None, secret)
+        .expand(INFO, &mut letters)
+        .expect("4 bytes is a valid HKDF output length");
+    for letter in letters.iter_mut() {
+        *letter = b'a' + *letter % 26;
+    }
+    ChunkType::new(letters).with_properties(false, false, true)
+}
+
+/// Derives the chunk type for a passphrase shared out of band.
+pub fn from_passphrase(passphrase: &str) -> ChunkType {
+    from_secret(passphrase.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derived_type_properties() {
+        for passphrase in ["", "team blue", "team red", "a much longer shared passphrase"] {
+            let chunk_type = from_passphrase(passphrase);
+            assert!(chunk_type.is_valid());
+            assert!(!chunk_type.is_critical());
+            assert!(!chunk_type.is_public());
+            assert!(chunk_type.is_safe_to_copy());
+            assert_eq!(chunk_type, from_passphrase(passphrase));
+        }
+        assert_ne!(from_passphrase("team blue"), from_passphrase("team red"));
+    }
+}
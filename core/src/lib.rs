@@ -1,6 +1,23 @@
+pub mod analysis;
+pub mod apng;
 pub mod chunk;
 pub mod chunk_type;
+pub mod encrypt;
+pub mod ecc;
+pub mod envelope;
+pub mod fragment;
+pub(crate) mod gf256;
+pub mod idat;
+pub mod ihdr;
+pub mod keyed_type;
+pub mod optimize;
+pub mod palette;
 pub mod png;
+pub mod recipient;
+pub mod registry;
+pub mod shamir;
+pub mod signature;
+pub mod stego;
 
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
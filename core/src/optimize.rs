@@ -0,0 +1,542 @@
+use std::collections::HashMap;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::idat::{FilterStrategy, Raster};
+use crate::ihdr::{ColorType, Ihdr};
+use crate::palette::{Palette, PaletteEntry};
+use crate::png::{self, Png};
+use crate::{Error, Result};
+
+/// Ancillary chunks that change how the image is rendered and are kept by
+/// default. Everything else ancillary is treated as redundant.
+pub const PRESERVED_CHUNKS: [&str; 8] = [
+    "gAMA", "cHRM", "sRGB", "iCCP", "cICP", "mDCV", "cLLI", "pHYs",
+];
+
+pub struct OptimizeOptions {
+    /// zlib compression levels to try (0-9).
+    pub levels: Vec<u32>,
+    pub strategies: Vec<FilterStrategy>,
+    /// Try lossless colour type and bit depth reductions.
+    pub reduce: bool,
+    /// Keep private ancillary chunks such as the ones `encode` writes.
+    pub keep_messages: bool,
+    /// Extra chunk types to keep on top of [`PRESERVED_CHUNKS`].
+    pub keep: Vec<ChunkType>,
+    /// Keep unknown unsafe-to-copy chunks even though the image data is
+    /// rewritten.
+    pub keep_unsafe: bool,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        OptimizeOptions {
+            levels: vec![6, 9],
+            strategies: FilterStrategy::ALL.to_vec(),
+            reduce: true,
+            keep_messages: false,
+            keep: Vec::new(),
+            keep_unsafe: false,
+        }
+    }
+}
+
+struct Candidate {
+    raster: Raster,
+    plte: Option<Chunk>,
+    trns: Option<Chunk>,
+}
+
+/// Losslessly recompresses `png`, returning the smallest encoding found.
+/// The original image data is itself a candidate, so the IDAT stream never
+/// grows.
+pub fn optimize(png: &Png, options: &OptimizeOptions) -> Result<Png> {
+    if png.chunk_by_type("acTL").is_some() {
+        return Err(Error::from("Animated PNGs can not be optimized"));
+    }
+    if options.levels.is_empty() || options.strategies.is_empty() {
+        return Err(Error::from("At least one level and filter strategy is required"));
+    }
+
+    let source = Raster::decode(png)?;
+    let original_plte = png.chunk_by_type("PLTE").cloned();
+    let original_trns = png.chunk_by_type("tRNS").cloned();
+
+    let mut candidates = Vec::new();
+    if options.reduce {
+        let pixels = expand(&source, png.palette()?.as_ref(), original_trns.as_ref())?;
+        candidates.extend(reduce(&pixels, source.width(), source.height())?);
+    }
+    candidates.push(Candidate {
+        raster: source,
+        plte: original_plte.clone(),
+        trns: original_trns.clone(),
+    });
+
+    // 原始 IDAT 本身也参与比较
+    let original_ihdr = png.ihdr()?;
+    let mut best = (
+        encoded_size(png.image_data().len(), &original_plte, &original_trns),
+        original_ihdr,
+        png.image_data(),
+        original_plte.clone(),
+        original_trns,
+    );
+
+    for candidate in candidates {
+        for &strategy in &options.strategies {
+            for &level in &options.levels {
+                let data = candidate.raster.encode(strategy, level)?;
+                let size = encoded_size(data.len(), &candidate.plte, &candidate.trns);
+                if size < best.0 {
+                    best = (
+                        size,
+                        *candidate.raster.ihdr(),
+                        data,
+                        candidate.plte.clone(),
+                        candidate.trns.clone(),
+                    );
+                }
+            }
+        }
+    }
+
+    let (_, ihdr, data, plte, trns) = best;
+    // 颜色类型、位深和调色板都不变时 sBIT、bKGD、hIST 原样保留，否则转换或丢弃
+    let unchanged = (ihdr.color_type, ihdr.bit_depth) == (original_ihdr.color_type, original_ihdr.bit_depth)
+        && plte == original_plte;
+    let new_palette = match &plte {
+        Some(plte) => Some(Palette::from_chunks(plte, trns.as_ref())?),
+        None => None,
+    };
+    let original_palette = png.palette()?;
+    let (sbit, bkgd) = match (png.chunk_by_type("sBIT"), png.chunk_by_type("bKGD")) {
+        (sbit, bkgd) if unchanged => (sbit.cloned(), bkgd.cloned()),
+        (sbit, bkgd) => (
+            sbit.and_then(|c| convert_sbit(c, &original_ihdr, &ihdr)),
+            bkgd.and_then(|c| convert_bkgd(c, &original_ihdr, original_palette.as_ref(), &ihdr, new_palette.as_ref())),
+        ),
+    };
+    // hIST 按调色板条目计数，调色板一变就失效
+    let hist = png
+        .chunk_by_type("hIST")
+        .filter(|_| plte.is_some() && plte == original_plte)
+        .cloned();
+
+    let mut chunks = Vec::new();
+    let mut wrote_image = false;
+    for chunk in png.chunks() {
+        let chunk_type = chunk.chunk_type();
+        match &chunk_type.bytes() {
+            b"IHDR" => chunks.push(ihdr.to_chunk()),
+            b"IDAT" if !wrote_image => {
+                chunks.extend(sbit.clone());
+                chunks.extend(plte.clone());
+                chunks.extend(trns.clone());
+                chunks.extend(bkgd.clone());
+                chunks.extend(hist.clone());
+                chunks.push(Chunk::new(chunk_type.clone(), data.clone()));
+                wrote_image = true;
+            }
+            // 这些块依赖于颜色类型，已在上面保留、转换或丢弃
+            b"IDAT" | b"PLTE" | b"tRNS" | b"sBIT" | b"bKGD" | b"hIST" => {}
+            _ if chunk_type.is_critical() => chunks.push(chunk.clone()),
+            _ if keep_ancillary(chunk_type, options) => chunks.push(chunk.clone()),
+            _ => {}
+        }
+    }
+    Ok(Png::from_chunks(chunks))
+}
+
+fn keep_ancillary(chunk_type: &ChunkType, options: &OptimizeOptions) -> bool {
+    let name = chunk_type.to_string();
+    let wanted = PRESERVED_CHUNKS.contains(&name.as_str())
+        || options.keep.contains(chunk_type)
+        || (options.keep_messages && !chunk_type.is_public());
+    // 图像数据被重写，依赖它的未知块不能保留
+    wanted && (options.keep_unsafe || !png::is_unsafe_to_copy(chunk_type))
+}
+
+// sBIT 按通道记录有效位数，先展开成 RGBA 再按新的颜色类型取出
+fn convert_sbit(chunk: &Chunk, from: &Ihdr, to: &Ihdr) -> Option<Chunk> {
+    let [r, g, b, a] = match (from.color_type, chunk.data()) {
+        (ColorType::Grayscale, &[v]) => [v, v, v, 0],
+        (ColorType::GrayscaleAlpha, &[v, a]) => [v, v, v, a],
+        (ColorType::Rgb | ColorType::Indexed, &[r, g, b]) => [r, g, b, 0],
+        (ColorType::Rgba, &[r, g, b, a]) => [r, g, b, a],
+        _ => return None,
+    };
+    let depth = if to.color_type == ColorType::Indexed { 8 } else { to.bit_depth };
+    // 原图没有 alpha 通道时，新 alpha 通道的每一位都有效
+    let a = if a == 0 { depth } else { a };
+    let values = match to.color_type {
+        ColorType::Grayscale => vec![r.max(g).max(b)],
+        ColorType::GrayscaleAlpha => vec![r.max(g).max(b), a],
+        ColorType::Rgb | ColorType::Indexed => vec![r, g, b],
+        ColorType::Rgba => vec![r, g, b, a],
+    };
+    let data = values.into_iter().map(|v| v.clamp(1, depth)).collect();
+    Some(Chunk::new(chunk.chunk_type().clone(), data))
+}
+
+// bKGD 转换为 16 位 RGB 后再写成新格式，无法精确表示时返回 None
+fn convert_bkgd(chunk: &Chunk, from: &Ihdr, from_palette: Option<&Palette>, to: &Ihdr, to_palette: Option<&Palette>) -> Option<Chunk> {
+    let data = chunk.data();
+    let scale = |depth: u8| 65535 / ((1u32 << depth) - 1);
+    let word = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]) as u32 * scale(from.bit_depth);
+    let rgb = match (from.color_type, data.len()) {
+        (ColorType::Grayscale | ColorType::GrayscaleAlpha, 2) => [word(0); 3],
+        (ColorType::Rgb | ColorType::Rgba, 6) => [word(0), word(2), word(4)],
+        (ColorType::Indexed, 1) => {
+            let entry = from_palette?.entries().get(data[0] as usize)?;
+            [entry.r, entry.g, entry.b].map(|v| v as u32 * 257)
+        }
+        _ => return None,
+    };
+
+    let data = match to.color_type {
+        ColorType::Indexed => {
+            let index = to_palette?
+                .entries()
+                .iter()
+                .position(|e| [e.r, e.g, e.b].map(|v| v as u32 * 257) == rgb)?;
+            vec![index as u8]
+        }
+        color_type => {
+            let step = scale(to.bit_depth);
+            if rgb.iter().any(|v| !v.is_multiple_of(step)) {
+                return None;
+            }
+            let samples = match color_type {
+                ColorType::Grayscale | ColorType::GrayscaleAlpha if rgb[0] == rgb[1] && rgb[1] == rgb[2] => &rgb[..1],
+                ColorType::Grayscale | ColorType::GrayscaleAlpha => return None,
+                _ => &rgb[..],
+            };
+            samples.iter().flat_map(|v| ((v / step) as u16).to_be_bytes()).collect()
+        }
+    };
+    Some(Chunk::new(chunk.chunk_type().clone(), data))
+}
+
+fn encoded_size(idat_len: usize, plte: &Option<Chunk>, trns: &Option<Chunk>) -> usize {
+    let extra: usize = [plte, trns]
+        .iter()
+        .filter_map(|c| c.as_ref())
+        .map(|c| c.length() as usize + Chunk::METADATA_SIZE)
+        .sum();
+    idat_len + extra
+}
+
+// 将任意格式的像素展开成 16 位 RGBA，便于统计
+pub(crate) fn expand(raster: &Raster, palette: Option<&Palette>, trns: Option<&Chunk>) -> Result<Vec<[u16; 4]>> {
+    let ihdr = raster.ihdr();
+    let scale = 65535 / ((1u32 << ihdr.bit_depth) - 1);
+    let key: Option<Vec<u16>> = match ihdr.color_type {
+        ColorType::Grayscale | ColorType::Rgb => trns.map(|c| {
+            c.data()
+                .chunks_exact(2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                .collect()
+        }),
+        _ => None,
+    };
+
+    let mut pixels = Vec::with_capacity((raster.width() * raster.height()) as usize);
+    for y in 0..raster.height() {
+        for x in 0..raster.width() {
+            let samples: Vec<u16> = (0..ihdr.color_type.channels())
+                .map(|c| raster.sample(x, y, c))
+                .collect();
+            let s = |v: u16| (v as u32 * scale) as u16;
+            let transparent = key.as_deref() == Some(&samples[..]);
+            let alpha = if transparent { 0 } else { 65535 };
+            let pixel = match ihdr.color_type {
+                ColorType::Grayscale => [s(samples[0]), s(samples[0]), s(samples[0]), alpha],
+                ColorType::GrayscaleAlpha => {
+                    [s(samples[0]), s(samples[0]), s(samples[0]), s(samples[1])]
+                }
+                ColorType::Rgb => [s(samples[0]), s(samples[1]), s(samples[2]), alpha],
+                ColorType::Rgba => [s(samples[0]), s(samples[1]), s(samples[2]), s(samples[3])],
+                ColorType::Indexed => {
+                    let palette = palette.ok_or("Indexed image without PLTE")?;
+                    let entry = palette
+                        .entries()
+                        .get(samples[0] as usize)
+                        .ok_or("Palette index out of range")?;
+                    [entry.r, entry.g, entry.b, entry.a].map(|v| v as u16 * 257)
+                }
+            };
+            pixels.push(pixel);
+        }
+    }
+    Ok(pixels)
+}
+
+fn reduce(pixels: &[[u16; 4]], width: u32, height: u32) -> Result<Vec<Candidate>> {
+    let needs_16 = pixels.iter().flatten().any(|&v| !v.is_multiple_of(257));
+    let opaque = pixels.iter().all(|p| p[3] == 65535);
+    let gray = pixels.iter().all(|p| p[0] == p[1] && p[1] == p[2]);
+
+    let color_type = match (gray, opaque) {
+        (true, true) => ColorType::Grayscale,
+        (true, false) => ColorType::GrayscaleAlpha,
+        (false, true) => ColorType::Rgb,
+        (false, false) => ColorType::Rgba,
+    };
+    let mut bit_depth = if needs_16 { 16 } else { 8 };
+    if color_type == ColorType::Grayscale && !needs_16 {
+        // 灰度图可以进一步降低到 1/2/4 位，只要所有值都能被精确表示
+        if let Some(&depth) = [1u8, 2, 4].iter().find(|&&d| {
+            let step = 65535 / ((1u32 << d) - 1);
+            pixels.iter().all(|p| (p[0] as u32).is_multiple_of(step))
+        }) {
+            bit_depth = depth;
+        }
+    }
+
+    let ihdr = Ihdr {
+        width,
+        height,
+        bit_depth,
+        color_type,
+        interlaced: false,
+    };
+    let scale = 65535 / ((1u32 << bit_depth) - 1);
+    let mut raster = Raster::new(ihdr, vec![0; ihdr.stride() * height as usize])?;
+    for (i, pixel) in pixels.iter().enumerate() {
+        let (x, y) = (i as u32 % width, i as u32 / width);
+        let channels: &[usize] = match color_type {
+            ColorType::Grayscale => &[0],
+            ColorType::GrayscaleAlpha => &[0, 3],
+            ColorType::Rgb => &[0, 1, 2],
+            _ => &[0, 1, 2, 3],
+        };
+        for (c, &source) in channels.iter().enumerate() {
+            raster.set_sample(x, y, c, (pixel[source] as u32 / scale) as u16);
+        }
+    }
+    let mut candidates = vec![Candidate {
+        raster,
+        plte: None,
+        trns: None,
+    }];
+
+    if !needs_16 {
+        if let Some(candidate) = to_indexed(pixels, width, height)? {
+            candidates.push(candidate);
+        }
+    }
+    Ok(candidates)
+}
+
+fn to_indexed(pixels: &[[u16; 4]], width: u32, height: u32) -> Result<Option<Candidate>> {
+    let mut colors: Vec<PaletteEntry> = Vec::new();
+    let mut seen = HashMap::new();
+    for pixel in pixels {
+        if !seen.contains_key(pixel) {
+            if colors.len() == Palette::MAX_ENTRIES {
+                return Ok(None);
+            }
+            let [r, g, b, a] = pixel.map(|v| (v / 257) as u8);
+            seen.insert(*pixel, colors.len());
+            colors.push(PaletteEntry { r, g, b, a });
+        }
+    }
+
+    // 透明条目排在前面，这样 tRNS 可以更短
+    let mut order: Vec<usize> = (0..colors.len()).collect();
+    order.sort_by_key(|&i| colors[i].a == 255);
+    let mut remap = vec![0; colors.len()];
+    for (new, &old) in order.iter().enumerate() {
+        remap[old] = new;
+    }
+    let palette = Palette::new(order.iter().map(|&i| colors[i]).collect())?;
+
+    let bit_depth = match palette.len() {
+        0..=2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        _ => 8,
+    };
+    let ihdr = Ihdr {
+        width,
+        height,
+        bit_depth,
+        color_type: ColorType::Indexed,
+        interlaced: false,
+    };
+    let mut raster = Raster::new(ihdr, vec![0; ihdr.stride() * height as usize])?;
+    for (i, pixel) in pixels.iter().enumerate() {
+        let index = remap[seen[pixel]];
+        raster.set_sample(i as u32 % width, i as u32 / width, 0, index as u16);
+    }
+
+    Ok(Some(Candidate {
+        raster,
+        plte: Some(palette.to_plte_chunk()),
+        trns: palette.to_trns_chunk(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry;
+    use std::str::FromStr;
+
+    fn testing_png(pixels: &[[u8; 4]], width: u32, extra: Vec<Chunk>) -> Png {
+        let ihdr = Ihdr {
+            width,
+            height: pixels.len() as u32 / width,
+            bit_depth: 8,
+            color_type: ColorType::Rgba,
+            interlaced: false,
+        };
+        let raster = Raster::new(ihdr, pixels.iter().flatten().copied().collect()).unwrap();
+        let mut chunks = vec![ihdr.to_chunk()];
+        chunks.extend(extra);
+        chunks.push(Chunk::new(
+            ChunkType::from_str("IDAT").unwrap(),
+            raster.encode(FilterStrategy::Fixed(crate::idat::FilterType::None), 0).unwrap(),
+        ));
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+        Png::from_chunks(chunks)
+    }
+
+    fn rendered(png: &Png) -> Vec<[u16; 4]> {
+        let raster = Raster::decode(png).unwrap();
+        expand(&raster, png.palette().unwrap().as_ref(), png.chunk_by_type("tRNS")).unwrap()
+    }
+
+    fn gradient() -> Vec<[u8; 4]> {
+        (0..64u32).map(|i| [(i * 4) as u8, (i * 3) as u8, (i * 2) as u8, 255]).collect()
+    }
+
+    #[test]
+    fn test_optimize_is_lossless() {
+        let png = testing_png(&gradient(), 8, Vec::new());
+        let optimized = optimize(&png, &OptimizeOptions::default()).unwrap();
+        assert!(optimized.as_bytes().len() < png.as_bytes().len());
+        assert_eq!(rendered(&optimized), rendered(&png));
+    }
+
+    #[test]
+    fn test_opaque_few_colors_become_indexed() {
+        let pixels: Vec<[u8; 4]> = (0..64).map(|i| [(i % 3) as u8 * 80, 10, 20, 255]).collect();
+        let png = testing_png(&pixels, 8, Vec::new());
+        let optimized = optimize(&png, &OptimizeOptions::default()).unwrap();
+
+        let ihdr = optimized.ihdr().unwrap();
+        assert_eq!(ihdr.color_type, ColorType::Indexed);
+        assert_eq!(ihdr.bit_depth, 2);
+        assert!(optimized.chunk_by_type("tRNS").is_none());
+        assert_eq!(rendered(&optimized), rendered(&png));
+    }
+
+    #[test]
+    fn test_opaque_gray_drops_alpha() {
+        let pixels: Vec<[u8; 4]> = (0..300u32).map(|i| [(i % 256) as u8; 3]).map(|[r, g, b]| [r, g, b, 255]).collect();
+        let png = testing_png(&pixels, 30, Vec::new());
+        let optimized = optimize(&png, &OptimizeOptions::default()).unwrap();
+
+        assert_eq!(optimized.ihdr().unwrap().color_type, ColorType::Grayscale);
+        assert_eq!(rendered(&optimized), rendered(&png));
+    }
+
+    #[test]
+    fn test_optimize_drops_redundant_chunks() {
+        let extra = vec![
+            Chunk::new(ChunkType::from_str("gAMA").unwrap(), vec![0, 0, 177, 143]),
+            Chunk::new(ChunkType::from_str("tEXt").unwrap(), b"Comment\0hello".to_vec()),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"secret".to_vec()),
+        ];
+        let png = testing_png(&gradient(), 8, extra);
+
+        let optimized = optimize(&png, &OptimizeOptions::default()).unwrap();
+        assert!(optimized.chunk_by_type("gAMA").is_some());
+        assert!(optimized.chunk_by_type("tEXt").is_none());
+        assert!(optimized.chunk_by_type("ruSt").is_none());
+
+        let options = OptimizeOptions {
+            keep_messages: true,
+            ..OptimizeOptions::default()
+        };
+        let optimized = optimize(&png, &options).unwrap();
+        assert_eq!(optimized.chunk_by_type("ruSt").unwrap().data_as_string().unwrap(), "secret");
+    }
+
+    #[test]
+    fn test_optimize_drops_unsafe_to_copy_chunks() {
+        let extra = vec![Chunk::new(ChunkType::from_str("ruST").unwrap(), b"stale".to_vec())];
+        let png = testing_png(&gradient(), 8, extra);
+        let mut options = OptimizeOptions {
+            keep_messages: true,
+            ..OptimizeOptions::default()
+        };
+        assert!(optimize(&png, &options).unwrap().chunk_by_type("ruST").is_none());
+
+        options.keep_unsafe = true;
+        assert!(optimize(&png, &options).unwrap().chunk_by_type("ruST").is_some());
+    }
+
+    #[test]
+    fn test_optimize_converts_sbit_and_bkgd() {
+        let pixels: Vec<[u8; 4]> = (0..300u32).map(|i| [(i % 256) as u8; 3]).map(|[r, g, b]| [r, g, b, 255]).collect();
+        let chunk = |t: &str, data: Vec<u8>| Chunk::new(ChunkType::from_str(t).unwrap(), data);
+        let extra = vec![chunk("sBIT", vec![5, 5, 5, 8]), chunk("bKGD", vec![0, 128, 0, 128, 0, 128])];
+        let optimized = optimize(&testing_png(&pixels, 30, extra), &OptimizeOptions::default()).unwrap();
+        assert_eq!(optimized.ihdr().unwrap().color_type, ColorType::Grayscale);
+        assert_eq!(optimized.chunk_by_type("sBIT").unwrap().data(), &[5]);
+        assert_eq!(optimized.chunk_by_type("bKGD").unwrap().data(), &[0, 128]);
+
+        // 灰度图无法表示彩色背景
+        let extra = vec![chunk("bKGD", vec![0, 128, 0, 0, 0, 0])];
+        let optimized = optimize(&testing_png(&pixels, 30, extra), &OptimizeOptions::default()).unwrap();
+        assert!(optimized.chunk_by_type("bKGD").is_none());
+    }
+
+    #[test]
+    fn test_optimize_keeps_palette_metadata() {
+        let indexed = |indices: Vec<u8>| {
+            let ihdr = Ihdr {
+                width: 8,
+                height: 8,
+                bit_depth: 8,
+                color_type: ColorType::Indexed,
+                interlaced: false,
+            };
+            let raster = Raster::new(ihdr, indices).unwrap();
+            let chunk = |t: &str, data: Vec<u8>| Chunk::new(ChunkType::from_str(t).unwrap(), data);
+            Png::from_chunks(vec![
+                ihdr.to_chunk(),
+                chunk("sBIT", vec![6, 6, 6]),
+                chunk("PLTE", vec![255, 0, 0, 0, 255, 0, 0, 0, 255]),
+                chunk("bKGD", vec![2]),
+                chunk("hIST", vec![0, 1, 0, 2, 0, 3]),
+                chunk("IDAT", raster.encode(FilterStrategy::Fixed(crate::idat::FilterType::None), 0).unwrap()),
+                chunk("IEND", Vec::new()),
+            ])
+        };
+
+        let png = indexed((0..64).map(|i| i % 3).collect());
+        let options = OptimizeOptions {
+            reduce: false,
+            ..OptimizeOptions::default()
+        };
+        let optimized = optimize(&png, &options).unwrap();
+        for chunk_type in ["sBIT", "bKGD", "hIST"] {
+            assert_eq!(optimized.chunk_by_type(chunk_type), png.chunk_by_type(chunk_type));
+        }
+
+        // 只用到绿色和蓝色，调色板重建后 hIST 失效，bKGD 指向新的索引
+        let png = indexed((0..64u32).map(|i| 1 + (i.wrapping_mul(2654435761) >> 7) as u8 % 2).collect());
+        let optimized = optimize(&png, &OptimizeOptions::default()).unwrap();
+        assert_eq!(optimized.palette().unwrap().unwrap().len(), 2);
+        assert_eq!(optimized.chunk_by_type("sBIT").unwrap().data(), &[6, 6, 6]);
+        assert_eq!(optimized.chunk_by_type("bKGD").unwrap().data(), &[1]);
+        assert!(optimized.chunk_by_type("hIST").is_none());
+        assert!(registry::validate(&optimized).is_empty());
+        assert_eq!(rendered(&optimized), rendered(&png));
+    }
+}
@@ -0,0 +1,122 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PaletteEntry {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+/// The colours of an indexed image, merged from its PLTE and tRNS chunks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    entries: Vec<PaletteEntry>,
+}
+
+impl Palette {
+    pub const MAX_ENTRIES: usize = 256;
+
+    pub fn new(entries: Vec<PaletteEntry>) -> Result<Palette> {
+        if entries.is_empty() || entries.len() > Palette::MAX_ENTRIES {
+            return Err(Error::from("Palette must have between 1 and 256 entries"));
+        }
+        Ok(Palette { entries })
+    }
+
+    pub fn from_chunks(plte: &Chunk, trns: Option<&Chunk>) -> Result<Palette> {
+        let data = plte.data();
+        if !data.len().is_multiple_of(3) {
+            return Err(Error::from("PLTE length must be a multiple of 3"));
+        }
+        let mut entries: Vec<PaletteEntry> = data
+            .chunks_exact(3)
+            .map(|rgb| PaletteEntry {
+                r: rgb[0],
+                g: rgb[1],
+                b: rgb[2],
+                a: 255,
+            })
+            .collect();
+
+        if let Some(trns) = trns {
+            if trns.data().len() > entries.len() {
+                return Err(Error::from("tRNS has more entries than PLTE"));
+            }
+            for (entry, &alpha) in entries.iter_mut().zip(trns.data()) {
+                entry.a = alpha;
+            }
+        }
+        Palette::new(entries)
+    }
+
+    pub fn entries(&self) -> &[PaletteEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn is_opaque(&self) -> bool {
+        self.entries.iter().all(|e| e.a == 255)
+    }
+
+    pub fn to_plte_chunk(&self) -> Chunk {
+        let data = self.entries.iter().flat_map(|e| [e.r, e.g, e.b]).collect();
+        Chunk::new(ChunkType::PLTE, data)
+    }
+
+    /// Builds the matching tRNS chunk, or `None` when every entry is opaque.
+    pub fn to_trns_chunk(&self) -> Option<Chunk> {
+        // 末尾不透明的条目可以省略
+        let len = self.entries.iter().rposition(|e| e.a != 255)? + 1;
+        let data = self.entries[..len].iter().map(|e| e.a).collect();
+        Some(Chunk::new(ChunkType::TRNS, data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(r: u8, g: u8, b: u8, a: u8) -> PaletteEntry {
+        PaletteEntry { r, g, b, a }
+    }
+
+    #[test]
+    fn test_palette_from_chunks() {
+        let plte = Chunk::new(ChunkType::PLTE, vec![1, 2, 3, 4, 5, 6]);
+        let trns = Chunk::new(ChunkType::TRNS, vec![0]);
+        let palette = Palette::from_chunks(&plte, Some(&trns)).unwrap();
+        assert_eq!(palette.entries(), &[entry(1, 2, 3, 0), entry(4, 5, 6, 255)]);
+    }
+
+    #[test]
+    fn test_palette_round_trip() {
+        let palette = Palette::new(vec![entry(1, 2, 3, 10), entry(4, 5, 6, 255)]).unwrap();
+        let plte = palette.to_plte_chunk();
+        let trns = palette.to_trns_chunk().unwrap();
+        assert_eq!(trns.length(), 1);
+        assert_eq!(Palette::from_chunks(&plte, Some(&trns)).unwrap(), palette);
+    }
+
+    #[test]
+    fn test_opaque_palette_has_no_trns() {
+        let palette = Palette::new(vec![entry(1, 2, 3, 255)]).unwrap();
+        assert!(palette.is_opaque());
+        assert!(palette.to_trns_chunk().is_none());
+    }
+
+    #[test]
+    fn test_invalid_plte_length() {
+        let plte = Chunk::new(ChunkType::PLTE, vec![1, 2]);
+        assert!(Palette::from_chunks(&plte, None).is_err());
+    }
+}
@@ -1,20 +1,106 @@
 
 
+use crate::apng::{self, Animation};
 use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::Ihdr;
+use crate::palette::Palette;
+use crate::registry::{self, KnownChunkType};
 use crate::{Error, Result};
 
 pub struct Png {
     chunks: Vec<Chunk>,
 }
 
+/// Which ancillary chunks [`Png::strip`] removes. Critical chunks are never
+/// removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StripPolicy {
+    /// Chunk types to remove, `None` for every ancillary chunk.
+    pub remove: Option<Vec<ChunkType>>,
+    /// Chunk types kept even if `remove` matches them.
+    pub keep: Vec<ChunkType>,
+    /// Keep private ancillary chunks such as encoded messages.
+    pub keep_messages: bool,
+}
+
+impl StripPolicy {
+    /// Chunks that change how the image renders or animates.
+    pub const RENDERING: [ChunkType; 12] = [
+        ChunkType::TRNS,
+        ChunkType::new(*b"gAMA"),
+        ChunkType::new(*b"cHRM"),
+        ChunkType::new(*b"sRGB"),
+        ChunkType::new(*b"iCCP"),
+        ChunkType::new(*b"sBIT"),
+        ChunkType::new(*b"cICP"),
+        ChunkType::new(*b"mDCV"),
+        ChunkType::new(*b"cLLI"),
+        ChunkType::new(*b"acTL"),
+        ChunkType::new(*b"fcTL"),
+        ChunkType::new(*b"fdAT"),
+    ];
+    /// Chunks that may reveal who made the image, when and where.
+    pub const PRIVACY: [ChunkType; 5] = [
+        ChunkType::new(*b"tIME"),
+        ChunkType::new(*b"eXIf"),
+        ChunkType::TEXT,
+        ChunkType::ZTXT,
+        ChunkType::ITXT,
+    ];
+
+    /// Removes the chunks in [`StripPolicy::PRIVACY`] and nothing else.
+    pub fn privacy() -> StripPolicy {
+        StripPolicy {
+            remove: Some(Self::PRIVACY.to_vec()),
+            ..StripPolicy::all_ancillary()
+        }
+    }
+
+    /// Removes every ancillary chunk.
+    pub fn all_ancillary() -> StripPolicy {
+        StripPolicy {
+            remove: None,
+            keep: Vec::new(),
+            keep_messages: false,
+        }
+    }
+
+    fn removes(&self, chunk_type: &ChunkType) -> bool {
+        let listed = match &self.remove {
+            Some(remove) => remove.contains(chunk_type),
+            None => true,
+        };
+        let kept = self.keep.contains(chunk_type) || (self.keep_messages && !chunk_type.is_public());
+        !chunk_type.is_critical() && listed && !kept
+    }
+}
+
+impl Default for StripPolicy {
+    /// Removes every ancillary chunk except [`StripPolicy::RENDERING`].
+    fn default() -> Self {
+        StripPolicy {
+            keep: Self::RENDERING.to_vec(),
+            ..StripPolicy::all_ancillary()
+        }
+    }
+}
+
 impl Png {
     pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
 
-    fn from_chunks(chunks: Vec<Chunk>) -> Png {
+    pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
         Png { chunks }
     }
 
     pub fn append_chunk(&mut self, chunk: Chunk) {
+        // 动画 PNG 插入到第一帧之前，避免打断 fcTL 和 fdAT 组成的帧序列
+        if self.chunk_by_type("acTL").is_some() {
+            if let Some(index) = self.chunks.iter().position(|c| apng::is_frame_chunk(c.chunk_type())) {
+                self.chunks.insert(index, chunk);
+                return;
+            }
+        }
         // 插入到倒数第二个位置，因为最后一个是IEND块,实际上放在IEND后面也不会有什么问题，但是依然遵循规范
         self.chunks.insert(self.chunks.len()-1 ,chunk);
     }
@@ -39,6 +125,108 @@ impl Png {
         self.chunks.iter().find(|c| c.chunk_type().to_string() == chunk_type)
     }
 
+    pub fn ihdr(&self) -> Result<Ihdr> {
+        let chunk = self.chunk_by_type("IHDR").ok_or("IHDR chunk not found")?;
+        Ihdr::try_from(chunk)
+    }
+
+    /// The frames of an animated PNG, `None` for a still image.
+    pub fn animation(&self) -> Result<Option<Animation>> {
+        apng::parse(self)
+    }
+
+    pub fn palette(&self) -> Result<Option<Palette>> {
+        match self.chunk_by_type("PLTE") {
+            Some(plte) => Palette::from_chunks(plte, self.chunk_by_type("tRNS")).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The concatenated data of all IDAT chunks, i.e. the complete zlib stream.
+    pub fn image_data(&self) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .filter(|c| *c.chunk_type() == ChunkType::IDAT)
+            .flat_map(|c| c.data().iter().copied())
+            .collect()
+    }
+
+    /// Rewrites IHDR and replaces all IDAT chunks with a single one holding
+    /// `zlib_data`, placed where the first IDAT used to be.
+    pub fn replace_image_data(&mut self, ihdr: &Ihdr, zlib_data: &[u8]) {
+        if let Some(header) = self.chunks.iter_mut().find(|c| *c.chunk_type() == ChunkType::IHDR) {
+            *header = ihdr.to_chunk();
+        }
+        let idat = Chunk::new(ChunkType::IDAT, zlib_data.to_vec());
+        self.replace_idat_chunks(vec![idat]);
+    }
+
+    /// Replaces all IDAT chunks with `idats`, placed where the first IDAT
+    /// used to be.
+    pub fn replace_idat_chunks(&mut self, idats: Vec<Chunk>) {
+        let index = self
+            .chunks
+            .iter()
+            .position(|c| *c.chunk_type() == ChunkType::IDAT)
+            .unwrap_or(self.chunks.len().saturating_sub(1));
+        self.chunks.retain(|c| *c.chunk_type() != ChunkType::IDAT);
+        self.chunks.splice(index..index, idats);
+    }
+
+    /// Replaces the first chunk of the same type as `chunk`, or appends it
+    /// when there is none.
+    pub fn replace_chunk(&mut self, chunk: Chunk) {
+        match self.chunks.iter_mut().find(|c| c.chunk_type() == chunk.chunk_type()) {
+            Some(existing) => *existing = chunk,
+            None => self.append_chunk(chunk),
+        }
+    }
+
+    /// Rewrites PLTE in place and replaces tRNS to match `palette`.
+    pub fn replace_palette(&mut self, palette: &Palette) {
+        self.chunks.retain(|c| *c.chunk_type() != ChunkType::TRNS);
+        let index = match self.chunks.iter().position(|c| *c.chunk_type() == ChunkType::PLTE) {
+            Some(i) => {
+                self.chunks[i] = palette.to_plte_chunk();
+                i
+            }
+            None => {
+                // PLTE 必须在第一个 IDAT 之前
+                let i = self
+                    .chunks
+                    .iter()
+                    .position(|c| *c.chunk_type() == ChunkType::IDAT)
+                    .unwrap_or(self.chunks.len().saturating_sub(1));
+                self.chunks.insert(i, palette.to_plte_chunk());
+                i
+            }
+        };
+        if let Some(trns) = palette.to_trns_chunk() {
+            self.chunks.insert(index + 1, trns);
+        }
+    }
+
+    /// Unknown ancillary chunks whose safe-to-copy bit is clear. They depend
+    /// on the image data, so the specification forbids copying them once a
+    /// critical chunk has changed.
+    pub fn unsafe_to_copy(&self) -> Vec<&Chunk> {
+        self.chunks.iter().filter(|c| is_unsafe_to_copy(c.chunk_type())).collect()
+    }
+
+    /// Removes the chunks listed by [`Png::unsafe_to_copy`] and returns them.
+    pub fn remove_unsafe_to_copy(&mut self) -> Vec<Chunk> {
+        let (removed, kept) = self.chunks.drain(..).partition(|c| is_unsafe_to_copy(c.chunk_type()));
+        self.chunks = kept;
+        removed
+    }
+
+    /// Removes the ancillary chunks selected by `policy` and returns them.
+    pub fn strip(&mut self, policy: &StripPolicy) -> Vec<Chunk> {
+        let (removed, kept) = self.chunks.drain(..).partition(|c| policy.removes(c.chunk_type()));
+        self.chunks = kept;
+        removed
+    }
+
     pub fn as_bytes(&self) -> Vec<u8>{
         let mut bytes = Vec::new();
         bytes.extend_from_slice(self.header());
@@ -54,6 +242,20 @@ impl TryFrom<&[u8]> for Png {
     type Error = Error;
 
     fn try_from(bytes: &[u8]) -> Result<Self> {
+        Png::parse(bytes, false).map(|(png, _)| png)
+    }
+}
+
+impl Png {
+    /// Parses `bytes` like [`Png::try_from`], but keeps ancillary chunks
+    /// whose CRC doesn't match instead of rejecting the whole file, so a
+    /// damaged error-corrected payload can still be repaired. Returns the
+    /// types of the damaged chunks alongside the image.
+    pub fn parse_lenient(bytes: &[u8]) -> Result<(Png, Vec<ChunkType>)> {
+        Png::parse(bytes, true)
+    }
+
+    fn parse(bytes: &[u8], lenient: bool) -> Result<(Png, Vec<ChunkType>)> {
         if bytes.len() < Png::STANDARD_HEADER.len() {
             return Err(Error::from("PNG data is too short"));
         }
@@ -64,16 +266,24 @@ impl TryFrom<&[u8]> for Png {
         }
 
         let mut chunks = Vec::new();
+        let mut damaged = Vec::new();
         let mut rest = rest;
 
         while !rest.is_empty() {
-            let chunk = Chunk::try_from(rest)?;
+            let (chunk, crc_matches) = Chunk::parse(rest)?;
+            if !crc_matches {
+                // 关键块损坏时图像本身无法使用，只放过辅助块
+                if !lenient || chunk.chunk_type().is_critical() {
+                    return Err(Error::from("Invalid CRC"));
+                }
+                damaged.push(chunk.chunk_type().clone());
+            }
             let chunk_size = Chunk::METADATA_SIZE + chunk.length() as usize;
             rest = &rest[chunk_size..];
             chunks.push(chunk);
         }
 
-        Ok(Png::from_chunks(chunks))
+        Ok((Png::from_chunks(chunks), damaged))
     }
 }
 
@@ -81,13 +291,20 @@ impl std::fmt::Display for Png {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "PNG HAS: {} chunks =>", self.chunks.len())?;
         for chunk in self.chunks() {
-            write!(f, "\n{}", chunk)?;
+            let data = chunk.data_as_string().unwrap_or_else(|_| "Invalid UTF-8".to_string());
+            write!(f, "\n{} ({}): {}", chunk.chunk_type(), registry::label(chunk.chunk_type()), data)?;
         }
         Ok(())
     }
 }
 
 
+// 已知的块由对应的操作自行处理，例如调色板隐写会重映射 bKGD
+pub(crate) fn is_unsafe_to_copy(chunk_type: &ChunkType) -> bool {
+    !chunk_type.is_critical()
+        && !chunk_type.is_safe_to_copy()
+        && KnownChunkType::from_chunk_type(chunk_type).is_none()
+}
 
 #[cfg(test)]
 mod tests {
@@ -95,6 +312,7 @@ mod tests {
     use crate::chunk_type::ChunkType;
     use crate::chunk::Chunk;
     use std::convert::TryFrom;
+    use std::str::FromStr;
 
     fn testing_chunks() -> Vec<Chunk> {
         vec![
@@ -184,6 +402,23 @@ mod tests {
         assert!(png.is_err());
     }
 
+    #[test]
+    fn test_parse_lenient() {
+        let mut bytes = testing_png().as_bytes();
+        // miDl 数据的第一个字节：文件头 8 字节，FrSt 块 12 + 20 字节，再加 miDl 的长度和类型
+        let offset = 8 + 32 + 8;
+        bytes[offset] ^= 0xff;
+        assert!(Png::try_from(bytes.as_ref()).is_err());
+
+        let (png, damaged) = Png::parse_lenient(&bytes).unwrap();
+        assert_eq!(damaged, vec![ChunkType::from_str("miDl").unwrap()]);
+        assert_eq!(png.chunks()[1].data()[1..], b"I am another chunk"[1..]);
+
+        // 关键块损坏时仍然拒绝
+        let mut bytes = testing_png().as_bytes();
+        bytes[8 + 8] ^= 0xff;
+        assert!(Png::parse_lenient(&bytes).is_err());
+    }
 
     #[test]
     fn test_list_chunks() {
@@ -219,6 +454,58 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_remove_unsafe_to_copy() {
+        let mut png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", "").unwrap(),
+            chunk_from_strings("ruST", "stale").unwrap(),
+            chunk_from_strings("tRNS", "").unwrap(),
+            chunk_from_strings("ruSt", "message").unwrap(),
+            chunk_from_strings("IEND", "").unwrap(),
+        ]);
+        let names = |chunks: &[&Chunk]| chunks.iter().map(|c| c.chunk_type().to_string()).collect::<Vec<_>>();
+        assert_eq!(names(&png.unsafe_to_copy()), vec!["ruST"]);
+        let removed = png.remove_unsafe_to_copy();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(png.chunks().len(), 4);
+        assert!(png.unsafe_to_copy().is_empty());
+    }
+
+    #[test]
+    fn test_append_keeps_out_of_frame_sequences() {
+        let types = ["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "IEND"];
+        let mut png = Png::from_chunks(types.iter().map(|t| chunk_from_strings(t, "").unwrap()).collect());
+        png.append_chunk(chunk_from_strings("ruSt", "").unwrap());
+        let names: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(names, vec!["IHDR", "acTL", "ruSt", "fcTL", "IDAT", "fcTL", "fdAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_strip() {
+        let png = || {
+            let types = ["IHDR", "gAMA", "tIME", "tRNS", "IDAT", "tEXt", "ruSt", "IEND"];
+            Png::from_chunks(types.iter().map(|t| chunk_from_strings(t, "").unwrap()).collect())
+        };
+        let stripped = |policy: &StripPolicy| {
+            let mut png = png();
+            png.strip(policy);
+            png.chunks().iter().map(|c| c.chunk_type().to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(stripped(&StripPolicy::default()), vec!["IHDR", "gAMA", "tRNS", "IDAT", "IEND"]);
+        assert_eq!(stripped(&StripPolicy::all_ancillary()), vec!["IHDR", "IDAT", "IEND"]);
+        assert_eq!(
+            stripped(&StripPolicy::privacy()),
+            vec!["IHDR", "gAMA", "tRNS", "IDAT", "ruSt", "IEND"]
+        );
+
+        let policy = StripPolicy {
+            keep: vec![ChunkType::TEXT],
+            keep_messages: true,
+            ..StripPolicy::all_ancillary()
+        };
+        assert_eq!(stripped(&policy), vec!["IHDR", "IDAT", "tEXt", "ruSt", "IEND"]);
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
@@ -0,0 +1,251 @@
+use std::fmt;
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+use crate::chunk_type::ChunkType;
+use crate::keyed_type;
+use crate::{Error, Result};
+
+pub const KEY_SIZE: usize = 32;
+pub const NONCE_SIZE: usize = 12;
+pub const TAG_SIZE: usize = 16;
+/// Size of the content key once wrapped for one recipient.
+pub const WRAPPED_KEY_SIZE: usize = KEY_SIZE + TAG_SIZE;
+
+const PUBLIC_PREFIX: &str = "pcm-public:";
+const SECRET_PREFIX: &str = "pcm-secret:";
+const WRAP_INFO: &[u8] = b"png-chunk-msg x25519 wrap";
+
+/// A public key that messages can be encrypted to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recipient {
+    key: PublicKey,
+}
+
+/// A secret key able to open messages sent to its [`Recipient`].
+pub struct Identity {
+    secret: StaticSecret,
+}
+
+impl Identity {
+    pub fn generate() -> Identity {
+        Identity {
+            secret: StaticSecret::random_from_rng(OsRng),
+        }
+    }
+
+    pub fn recipient(&self) -> Recipient {
+        Recipient {
+            key: PublicKey::from(&self.secret),
+        }
+    }
+
+    /// Chunk type shared with `peer`: both sides derive the same type from
+    /// their own identity and the other's public key.
+    pub fn shared_chunk_type(&self, peer: &Recipient) -> ChunkType {
+        let own = self.recipient();
+        // 公钥按字节排序，两边得到相同的输入
+        let mut keys = [&own.key.as_bytes()[..], &peer.key.as_bytes()[..]];
+        keys.sort();
+        let shared = self.secret.diffie_hellman(&peer.key);
+        let secret = Zeroizing::new([keys[0], keys[1], shared.as_bytes()].concat());
+        keyed_type::from_secret(&secret)
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", PUBLIC_PREFIX, BASE64.encode(self.key.as_bytes()))
+    }
+}
+
+impl FromStr for Recipient {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes = decode_key(s, PUBLIC_PREFIX)?;
+        Ok(Recipient {
+            key: PublicKey::from(*bytes),
+        })
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", SECRET_PREFIX, BASE64.encode(self.secret.as_bytes()))
+    }
+}
+
+impl FromStr for Identity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes = decode_key(s, SECRET_PREFIX)?;
+        Ok(Identity {
+            secret: StaticSecret::from(*bytes),
+        })
+    }
+}
+
+fn decode_key(s: &str, prefix: &str) -> Result<Zeroizing<[u8; KEY_SIZE]>> {
+    let encoded = s
+        .trim()
+        .strip_prefix(prefix)
+        .ok_or_else(|| Error::from(format!("Key must start with {}", prefix)))?;
+    let bytes = Zeroizing::new(BASE64.decode(encoded)?);
+    let mut key = Zeroizing::new([0u8; KEY_SIZE]);
+    if bytes.len() != KEY_SIZE {
+        return Err(Error::from("Key must be 32 bytes long"));
+    }
+    key.copy_from_slice(&bytes);
+    Ok(key)
+}
+
+/// Encrypts `plaintext` once with a random content key and wraps that key
+/// for every recipient. `associated_data` is authenticated along with the
+/// ciphertext but not stored.
+///
+/// Layout: `ephemeral public key | recipient count (u16) | wrapped keys |
+/// nonce | ciphertext | tag`. Wrapped keys carry no recipient id, so a
+/// reader tries each one with its identity.
+pub fn seal(recipients: &[Recipient], plaintext: &[u8], associated_data: &[u8]) -> Result<Vec<u8>> {
+    if recipients.is_empty() {
+        return Err(Error::from("At least one recipient is required"));
+    }
+    let count = u16::try_from(recipients.len()).map_err(|_| "Too many recipients")?;
+
+    let ephemeral = StaticSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+    let mut content_key = Zeroizing::new([0u8; KEY_SIZE]);
+    let mut nonce = [0u8; NONCE_SIZE];
+    OsRng.fill_bytes(&mut content_key[..]);
+    OsRng.fill_bytes(&mut nonce);
+
+    let mut sealed = Vec::new();
+    sealed.extend_from_slice(ephemeral_public.as_bytes());
+    sealed.extend_from_slice(&count.to_be_bytes());
+    for recipient in recipients {
+        let shared = ephemeral.diffie_hellman(&recipient.key);
+        let wrap_key = wrap_key(shared.as_bytes(), &ephemeral_public, &recipient.key)?;
+        // 每个包装密钥只使用一次，所以可以使用全零 nonce
+        let wrapped = ChaCha20Poly1305::new(Key::from_slice(&wrap_key[..]))
+            .encrypt(Nonce::from_slice(&[0; NONCE_SIZE]), &content_key[..])
+            .map_err(|_| Error::from("Encryption failed"))?;
+        sealed.extend_from_slice(&wrapped);
+    }
+
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&content_key[..]))
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: associated_data })
+        .map_err(|_| Error::from("Encryption failed"))?;
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Decrypts data produced by [`seal`] if `identity` is one of its recipients
+/// and `associated_data` is unchanged.
+pub fn open(identity: &Identity, sealed: &[u8], associated_data: &[u8]) -> Result<Vec<u8>> {
+    let header = KEY_SIZE + 2;
+    if sealed.len() < header {
+        return Err(Error::from("Encrypted data is too short"));
+    }
+    let ephemeral_public: [u8; KEY_SIZE] = sealed[..KEY_SIZE].try_into()?;
+    let ephemeral_public = PublicKey::from(ephemeral_public);
+    let count = u16::from_be_bytes([sealed[KEY_SIZE], sealed[KEY_SIZE + 1]]) as usize;
+    let body = header + count * WRAPPED_KEY_SIZE;
+    if sealed.len() < body + NONCE_SIZE + TAG_SIZE {
+        return Err(Error::from("Encrypted data is too short"));
+    }
+
+    let own = identity.recipient();
+    let shared = identity.secret.diffie_hellman(&ephemeral_public);
+    let wrap_key = wrap_key(shared.as_bytes(), &ephemeral_public, &own.key)?;
+    let wrapper = ChaCha20Poly1305::new(Key::from_slice(&wrap_key[..]));
+    let content_key = sealed[header..body]
+        .chunks_exact(WRAPPED_KEY_SIZE)
+        .find_map(|wrapped| wrapper.decrypt(Nonce::from_slice(&[0; NONCE_SIZE]), wrapped).ok())
+        .map(Zeroizing::new)
+        .ok_or("This identity is not a recipient of the message")?;
+
+    let (nonce, ciphertext) = sealed[body..].split_at(NONCE_SIZE);
+    ChaCha20Poly1305::new(Key::from_slice(&content_key))
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: associated_data })
+        .map_err(|_| Error::from("Decryption failed: corrupted data"))
+}
+
+fn wrap_key(shared: &[u8], ephemeral: &PublicKey, recipient: &PublicKey) -> Result<Zeroizing<[u8; KEY_SIZE]>> {
+    let mut salt = [0u8; 2 * KEY_SIZE];
+    salt[..KEY_SIZE].copy_from_slice(ephemeral.as_bytes());
+    salt[KEY_SIZE..].copy_from_slice(recipient.as_bytes());
+    let mut key = Zeroizing::new([0u8; KEY_SIZE]);
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(WRAP_INFO, &mut key[..])
+        .map_err(|_| Error::from("Key derivation failed"))?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_for_several_recipients() {
+        let alice = Identity::generate();
+        let bob = Identity::generate();
+        let sealed = seal(&[alice.recipient(), bob.recipient()], b"team secret", b"").unwrap();
+
+        assert_eq!(sealed.len(), 34 + 2 * WRAPPED_KEY_SIZE + NONCE_SIZE + 11 + TAG_SIZE);
+        assert_eq!(open(&alice, &sealed, b"").unwrap(), b"team secret");
+        assert_eq!(open(&bob, &sealed, b"").unwrap(), b"team secret");
+    }
+
+    #[test]
+    fn test_shared_chunk_type() {
+        // 固定的密钥，结果是确定的
+        let identity = |byte: u8| Identity::from_str(&format!("{}{}", SECRET_PREFIX, BASE64.encode([byte; KEY_SIZE]))).unwrap();
+        let (alice, bob, carol) = (identity(1), identity(2), identity(3));
+        let chunk_type = alice.shared_chunk_type(&bob.recipient());
+        assert_eq!(chunk_type, bob.shared_chunk_type(&alice.recipient()));
+        assert!(!chunk_type.is_public() && !chunk_type.is_critical());
+        assert_ne!(chunk_type, alice.shared_chunk_type(&carol.recipient()));
+    }
+
+    #[test]
+    fn test_open_as_non_recipient() {
+        let alice = Identity::generate();
+        let mallory = Identity::generate();
+        let sealed = seal(&[alice.recipient()], b"team secret", b"").unwrap();
+        assert!(open(&mallory, &sealed, b"").is_err());
+    }
+
+    #[test]
+    fn test_open_tampered() {
+        let alice = Identity::generate();
+        let mut sealed = seal(&[alice.recipient()], b"team secret", b"").unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(open(&alice, &sealed, b"").is_err());
+    }
+
+    #[test]
+    fn test_key_strings_round_trip() {
+        let identity = Identity::generate();
+        let recipient = identity.recipient();
+
+        let parsed = Identity::from_str(&identity.to_string()).unwrap();
+        assert_eq!(parsed.recipient(), recipient);
+        assert_eq!(Recipient::from_str(&recipient.to_string()).unwrap(), recipient);
+        assert!(Recipient::from_str(&identity.to_string()).is_err());
+        assert!(Recipient::from_str("pcm-public:AAAA").is_err());
+    }
+}
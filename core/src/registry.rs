@@ -0,0 +1,326 @@
+//! Chunk types defined by the PNG specification (third edition), APNG and
+//! common extensions, with the rules each one follows.
+
+use std::fmt;
+
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+
+/// Where a chunk may appear in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    /// Before IHDR, only used by Apple's CgBI.
+    BeforeIhdr,
+    /// The first chunk after the signature.
+    First,
+    /// Before PLTE and the image data.
+    BeforePlte,
+    /// After PLTE, if there is one, and before the image data.
+    AfterPlteBeforeIdat,
+    /// Before the image data.
+    BeforeIdat,
+    /// Image data, in consecutive chunks.
+    Idat,
+    /// After the image data.
+    AfterIdat,
+    /// Anywhere between IHDR and IEND.
+    Anywhere,
+    /// The last chunk.
+    Last,
+}
+
+impl fmt::Display for Placement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rule = match self {
+            Placement::BeforeIhdr => "before IHDR",
+            Placement::First => "first",
+            Placement::BeforePlte => "before PLTE and IDAT",
+            Placement::AfterPlteBeforeIdat => "after PLTE and before IDAT",
+            Placement::BeforeIdat => "before IDAT",
+            Placement::Idat => "in consecutive IDAT chunks",
+            Placement::AfterIdat => "after IDAT",
+            Placement::Anywhere => "between IHDR and IEND",
+            Placement::Last => "last",
+        };
+        write!(f, "{}", rule)
+    }
+}
+
+/// How many chunks of a type a file may hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Multiplicity {
+    Once,
+    AtMostOnce,
+    AtLeastOnce,
+    Any,
+}
+
+/// Which document defines a chunk type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Standard {
+    Png,
+    Apng,
+    Extension,
+}
+
+macro_rules! known_chunk_types {
+    ($($variant:ident => $bytes:literal, $standard:ident, $placement:ident, $multiplicity:ident, $description:literal;)*) => {
+        /// A chunk type with a published definition.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum KnownChunkType {
+            $($variant,)*
+        }
+
+        impl KnownChunkType {
+            pub const ALL: &'static [KnownChunkType] = &[$(KnownChunkType::$variant,)*];
+
+            pub const fn chunk_type(self) -> ChunkType {
+                match self {
+                    $(KnownChunkType::$variant => ChunkType::new(*$bytes),)*
+                }
+            }
+
+            pub fn standard(self) -> Standard {
+                match self {
+                    $(KnownChunkType::$variant => Standard::$standard,)*
+                }
+            }
+
+            pub fn placement(self) -> Placement {
+                match self {
+                    $(KnownChunkType::$variant => Placement::$placement,)*
+                }
+            }
+
+            pub fn multiplicity(self) -> Multiplicity {
+                match self {
+                    $(KnownChunkType::$variant => Multiplicity::$multiplicity,)*
+                }
+            }
+
+            pub fn description(self) -> &'static str {
+                match self {
+                    $(KnownChunkType::$variant => $description,)*
+                }
+            }
+        }
+    };
+}
+
+known_chunk_types! {
+    Ihdr => b"IHDR", Png, First, Once, "Image header";
+    Plte => b"PLTE", Png, BeforeIdat, AtMostOnce, "Palette";
+    Idat => b"IDAT", Png, Idat, AtLeastOnce, "Image data";
+    Iend => b"IEND", Png, Last, Once, "Image trailer";
+    Trns => b"tRNS", Png, AfterPlteBeforeIdat, AtMostOnce, "Transparency";
+    Chrm => b"cHRM", Png, BeforePlte, AtMostOnce, "Primary chromaticities and white point";
+    Gama => b"gAMA", Png, BeforePlte, AtMostOnce, "Image gamma";
+    Iccp => b"iCCP", Png, BeforePlte, AtMostOnce, "Embedded ICC profile";
+    Sbit => b"sBIT", Png, BeforePlte, AtMostOnce, "Significant bits";
+    Srgb => b"sRGB", Png, BeforePlte, AtMostOnce, "Standard RGB colour space";
+    Cicp => b"cICP", Png, BeforePlte, AtMostOnce, "Coding-independent code points";
+    Mdcv => b"mDCV", Png, BeforePlte, AtMostOnce, "Mastering display colour volume";
+    Clli => b"cLLI", Png, BeforePlte, AtMostOnce, "Content light level information";
+    Text => b"tEXt", Png, Anywhere, Any, "Textual data";
+    Ztxt => b"zTXt", Png, Anywhere, Any, "Compressed textual data";
+    Itxt => b"iTXt", Png, Anywhere, Any, "International textual data";
+    Bkgd => b"bKGD", Png, AfterPlteBeforeIdat, AtMostOnce, "Background colour";
+    Hist => b"hIST", Png, AfterPlteBeforeIdat, AtMostOnce, "Image histogram";
+    Phys => b"pHYs", Png, BeforeIdat, AtMostOnce, "Physical pixel dimensions";
+    Splt => b"sPLT", Png, BeforeIdat, Any, "Suggested palette";
+    Exif => b"eXIf", Png, BeforeIdat, AtMostOnce, "Exchangeable image file profile";
+    Time => b"tIME", Png, Anywhere, AtMostOnce, "Image last-modification time";
+    Actl => b"acTL", Apng, BeforeIdat, AtMostOnce, "Animation control";
+    Fctl => b"fcTL", Apng, Anywhere, Any, "Frame control";
+    Fdat => b"fdAT", Apng, AfterIdat, Any, "Frame data";
+    Offs => b"oFFs", Extension, BeforeIdat, AtMostOnce, "Image offset";
+    Pcal => b"pCAL", Extension, BeforeIdat, AtMostOnce, "Calibration of pixel values";
+    Scal => b"sCAL", Extension, BeforeIdat, AtMostOnce, "Physical scale of image subject";
+    Gifg => b"gIFg", Extension, Anywhere, Any, "GIF graphic control extension";
+    Gifx => b"gIFx", Extension, Anywhere, Any, "GIF application extension";
+    Gift => b"gIFt", Extension, Anywhere, Any, "GIF plain text extension (deprecated)";
+    Ster => b"sTER", Extension, BeforeIdat, AtMostOnce, "Indicator of stereo image";
+    Dsig => b"dSIG", Extension, Anywhere, Any, "Digital signature";
+    Frac => b"fRAc", Extension, Anywhere, Any, "Fractal image parameters";
+    Cgbi => b"CgBI", Extension, BeforeIhdr, AtMostOnce, "Apple optimized PNG";
+    Idot => b"iDOT", Extension, BeforeIdat, AtMostOnce, "Apple multithreaded decoding";
+    Vpag => b"vpAg", Extension, BeforeIdat, AtMostOnce, "ImageMagick virtual page";
+    Canv => b"caNv", Extension, BeforeIdat, AtMostOnce, "ImageMagick virtual canvas";
+    Ornt => b"orNT", Extension, BeforeIdat, AtMostOnce, "ImageMagick orientation";
+}
+
+impl KnownChunkType {
+    pub fn from_chunk_type(chunk_type: &ChunkType) -> Option<KnownChunkType> {
+        KnownChunkType::ALL
+            .iter()
+            .copied()
+            .find(|known| known.chunk_type() == *chunk_type)
+    }
+}
+
+/// Short human readable label for any chunk type.
+pub fn label(chunk_type: &ChunkType) -> String {
+    match KnownChunkType::from_chunk_type(chunk_type) {
+        Some(known) => known.description().to_string(),
+        None if chunk_type.is_critical() => "unknown critical chunk".to_string(),
+        None if chunk_type.is_public() => "unknown public chunk".to_string(),
+        None => "private chunk".to_string(),
+    }
+}
+
+/// A way in which a file breaks the rules of the specification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    Missing(ChunkType),
+    Repeated(ChunkType),
+    Misplaced(ChunkType, Placement),
+    /// IDAT chunks separated by other chunks.
+    IdatNotConsecutive,
+    /// Third letter is lowercase.
+    ReservedBit(ChunkType),
+    /// Decoders must reject images with critical chunks they don't know.
+    UnknownCritical(ChunkType),
+    /// Public types are reserved for the specification.
+    UnknownPublic(ChunkType),
+    /// The APNG frame sequence is broken.
+    Animation(String),
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::Missing(chunk_type) => write!(f, "{} is required but missing", chunk_type),
+            Issue::Repeated(chunk_type) => write!(f, "{} appears more than once", chunk_type),
+            Issue::Misplaced(chunk_type, placement) => write!(f, "{} must appear {}", chunk_type, placement),
+            Issue::IdatNotConsecutive => write!(f, "IDAT chunks are not consecutive"),
+            Issue::ReservedBit(chunk_type) => write!(f, "{} has the reserved bit set", chunk_type),
+            Issue::UnknownCritical(chunk_type) => write!(f, "{} is an unknown critical chunk", chunk_type),
+            Issue::UnknownPublic(chunk_type) => write!(f, "{} is an unknown public chunk", chunk_type),
+            Issue::Animation(error) => write!(f, "Invalid animation: {}", error),
+        }
+    }
+}
+
+/// Checks chunk order, multiplicity and naming against the registry.
+pub fn validate(png: &Png) -> Vec<Issue> {
+    let chunks = png.chunks();
+    let position = |chunk_type: &ChunkType| chunks.iter().position(|c| c.chunk_type() == chunk_type);
+    let plte = position(&ChunkType::PLTE);
+    let first_idat = position(&ChunkType::IDAT);
+    let last_idat = chunks.iter().rposition(|c| *c.chunk_type() == ChunkType::IDAT);
+    // CgBI 可以出现在 IHDR 之前
+    let start = position(&ChunkType::IHDR).unwrap_or(0);
+
+    let mut issues = Vec::new();
+    let mut reported: Vec<ChunkType> = Vec::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let chunk_type = chunk.chunk_type();
+        if !chunk_type.is_reserved_bit_valid() {
+            issues.push(Issue::ReservedBit(chunk_type.clone()));
+        }
+        let Some(known) = KnownChunkType::from_chunk_type(chunk_type) else {
+            if chunk_type.is_critical() {
+                issues.push(Issue::UnknownCritical(chunk_type.clone()));
+            } else if chunk_type.is_public() {
+                issues.push(Issue::UnknownPublic(chunk_type.clone()));
+            }
+            continue;
+        };
+        let before = |end: Option<usize>| end.is_none_or(|end| i < end);
+        let inside = i > start && i + 1 < chunks.len();
+        let placed = match known.placement() {
+            Placement::BeforeIhdr => i < start,
+            Placement::First => i == start,
+            Placement::BeforePlte => inside && before(plte) && before(first_idat),
+            Placement::AfterPlteBeforeIdat => inside && plte.is_none_or(|p| i > p) && before(first_idat),
+            Placement::BeforeIdat => inside && before(first_idat),
+            Placement::Idat => true,
+            Placement::AfterIdat => inside && last_idat.is_some_and(|last| i > last),
+            Placement::Anywhere => inside,
+            Placement::Last => i + 1 == chunks.len(),
+        };
+        if !placed && !reported.contains(chunk_type) {
+            issues.push(Issue::Misplaced(chunk_type.clone(), known.placement()));
+            reported.push(chunk_type.clone());
+        }
+    }
+
+    for &known in KnownChunkType::ALL {
+        let chunk_type = known.chunk_type();
+        let count = chunks.iter().filter(|c| *c.chunk_type() == chunk_type).count();
+        match known.multiplicity() {
+            Multiplicity::Once | Multiplicity::AtLeastOnce if count == 0 => issues.push(Issue::Missing(chunk_type)),
+            Multiplicity::Once | Multiplicity::AtMostOnce if count > 1 => issues.push(Issue::Repeated(chunk_type)),
+            _ => {}
+        }
+    }
+    if let (Some(first), Some(last)) = (first_idat, last_idat) {
+        if chunks[first..=last].iter().any(|c| *c.chunk_type() != ChunkType::IDAT) {
+            issues.push(Issue::IdatNotConsecutive);
+        }
+    }
+    if let Err(e) = png.animation() {
+        issues.push(Issue::Animation(e.to_string()));
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use std::str::FromStr;
+
+    fn png(types: &[&str]) -> Png {
+        Png::from_chunks(
+            types
+                .iter()
+                .map(|t| Chunk::new(ChunkType::from_str(t).unwrap(), Vec::new()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_registry_lookup() {
+        for &known in KnownChunkType::ALL {
+            let chunk_type = known.chunk_type();
+            assert!(chunk_type.is_valid(), "{}", chunk_type);
+            assert_eq!(KnownChunkType::from_chunk_type(&chunk_type), Some(known));
+        }
+        assert_eq!(KnownChunkType::from_chunk_type(&ChunkType::TRNS), Some(KnownChunkType::Trns));
+        assert_eq!(KnownChunkType::Fdat.standard(), Standard::Apng);
+        assert_eq!(label(&ChunkType::IDAT), "Image data");
+        assert_eq!(label(&ChunkType::from_str("ruSt").unwrap()), "private chunk");
+        assert_eq!(label(&ChunkType::from_str("aBCd").unwrap()), "unknown public chunk");
+    }
+
+    #[test]
+    fn test_valid_file() {
+        let file = png(&["IHDR", "gAMA", "PLTE", "tRNS", "tEXt", "IDAT", "IDAT", "ruSt", "tEXt", "IEND"]);
+        assert_eq!(validate(&file), Vec::new());
+        let apple = png(&["CgBI", "IHDR", "IDAT", "IEND"]);
+        assert_eq!(validate(&apple), Vec::new());
+        // 顺序正确，但空的 acTL 无法解析
+        let animated = png(&["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "IEND"]);
+        assert_eq!(validate(&animated), vec![Issue::Animation("Invalid acTL length".to_string())]);
+    }
+
+    #[test]
+    fn test_invalid_file() {
+        let file = png(&["gAMA", "IHDR", "PLTE", "gAMA", "IDAT", "tIME", "IDAT", "tIME", "aBCd", "QqQq", "IEND"]);
+        let issues: Vec<String> = validate(&file).iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            issues,
+            vec![
+                "gAMA must appear before PLTE and IDAT",
+                "aBCd is an unknown public chunk",
+                "QqQq is an unknown critical chunk",
+                "gAMA appears more than once",
+                "tIME appears more than once",
+                "IDAT chunks are not consecutive",
+            ]
+        );
+        let empty = png(&["IHDR", "IEND"]);
+        assert_eq!(validate(&empty), vec![Issue::Missing(ChunkType::IDAT)]);
+    }
+}
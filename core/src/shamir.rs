@@ -0,0 +1,253 @@
+use rand::RngCore;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::envelope::Envelope;
+use crate::gf256;
+use crate::png::Png;
+use crate::{Error, Result};
+
+/// Content type of an envelope holding one share.
+pub const SHARE_CONTENT_TYPE: &str = "application/x-pcm-share";
+
+/// Describes one share of a secret. Stored in the envelope header, outside
+/// the share data itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShareInfo {
+    /// Random id shared by all shares of one secret.
+    pub set_id: [u8; 8],
+    /// x coordinate of the share, from 1 to `count`.
+    pub index: u8,
+    pub threshold: u8,
+    pub count: u8,
+}
+
+impl ShareInfo {
+    pub const SIZE: usize = 8 + 3;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Share {
+    pub info: ShareInfo,
+    pub data: Vec<u8>,
+}
+
+/// Splits `secret` into `count` shares so that any `threshold` of them
+/// recover it and fewer reveal nothing about it.
+pub fn split(secret: &[u8], threshold: u8, count: u8) -> Result<Vec<Share>> {
+    if threshold == 0 || threshold > count {
+        return Err(Error::from("Threshold must be between 1 and the number of shares"));
+    }
+    let mut rng = rand::thread_rng();
+    let mut set_id = [0u8; 8];
+    rng.fill_bytes(&mut set_id);
+
+    let mut shares: Vec<Share> = (1..=count)
+        .map(|index| Share {
+            info: ShareInfo {
+                set_id,
+                index,
+                threshold,
+                count,
+            },
+            data: Vec::with_capacity(secret.len()),
+        })
+        .collect();
+    // 每个字节一个 threshold-1 次的随机多项式，常数项就是该字节
+    let mut poly = vec![0u8; threshold as usize];
+    for &byte in secret {
+        rng.fill_bytes(&mut poly[..threshold as usize - 1]);
+        poly[threshold as usize - 1] = byte;
+        for share in shares.iter_mut() {
+            share.data.push(gf256::eval(&poly, share.info.index));
+        }
+    }
+    Ok(shares)
+}
+
+/// Recovers the secret from at least `threshold` shares of the same set.
+pub fn combine(shares: &[Share]) -> Result<Vec<u8>> {
+    let first = shares.first().ok_or("No shares provided")?;
+    let info = first.info;
+    if info.threshold == 0 || info.threshold > info.count {
+        return Err(Error::from("Invalid share threshold"));
+    }
+    let mut unique: Vec<&Share> = Vec::new();
+    for share in shares {
+        if share.info.set_id != info.set_id {
+            return Err(Error::from("Shares belong to more than one secret"));
+        }
+        // 同一组的分片必须给出相同的门限、总数和长度
+        if (share.info.threshold, share.info.count, share.data.len()) != (info.threshold, info.count, first.data.len()) {
+            return Err(Error::from("Shares of the same secret disagree"));
+        }
+        if share.info.index == 0 || share.info.index > info.count {
+            return Err(Error::from("Invalid share"));
+        }
+        match unique.iter().find(|s| s.info.index == share.info.index) {
+            Some(existing) if existing.data != share.data => {
+                return Err(Error::from(format!("Conflicting copies of share {}", share.info.index)));
+            }
+            Some(_) => {}
+            None => unique.push(share),
+        }
+    }
+    let threshold = first.info.threshold as usize;
+    if unique.len() < threshold {
+        return Err(Error::from(format!(
+            "Need {} of {} shares to recover the message, only {} provided",
+            threshold,
+            first.info.count,
+            unique.len()
+        )));
+    }
+    let unique = &unique[..threshold];
+
+    // 拉格朗日插值在 x = 0 处的系数
+    let weights: Vec<u8> = unique
+        .iter()
+        .map(|share| {
+            let xi = share.info.index;
+            unique
+                .iter()
+                .filter(|other| other.info.index != xi)
+                .fold(1, |acc, other| {
+                    let xj = other.info.index;
+                    gf256::mul(acc, gf256::div(xj, xj ^ xi))
+                })
+        })
+        .collect();
+    Ok((0..first.data.len())
+        .map(|i| {
+            unique
+                .iter()
+                .zip(&weights)
+                .fold(0, |acc, (share, &w)| acc ^ gf256::mul(share.data[i], w))
+        })
+        .collect())
+}
+
+impl Share {
+    pub fn to_envelope(&self) -> Envelope {
+        let mut envelope = Envelope::new(SHARE_CONTENT_TYPE, self.data.clone());
+        envelope.share = Some(self.info);
+        envelope
+    }
+
+    /// Reads a share from envelope bytes, `None` if they hold something else.
+    pub fn detect(bytes: &[u8]) -> Result<Option<Share>> {
+        Ok(Envelope::detect(bytes)?.and_then(|envelope| {
+            envelope.share.map(|info| Share {
+                info,
+                data: envelope.body,
+            })
+        }))
+    }
+}
+
+/// Splits `payload` into one share per image, any `threshold` of which
+/// recover it.
+pub fn embed_across(pngs: &mut [Png], chunk_type: &ChunkType, payload: &[u8], threshold: u8) -> Result<()> {
+    let count = u8::try_from(pngs.len()).map_err(|_| "At most 255 shares are supported")?;
+    for (png, share) in pngs.iter_mut().zip(split(payload, threshold, count)?) {
+        png.append_chunk(Chunk::new(chunk_type.clone(), share.to_envelope().to_bytes()?));
+    }
+    Ok(())
+}
+
+/// Recovers a payload written by [`embed_across`] from any `threshold` of
+/// the images.
+pub fn extract_across(pngs: &[Png], chunk_type: &str) -> Result<Vec<u8>> {
+    let mut shares = Vec::new();
+    for png in pngs {
+        for chunk in png.chunks().iter().filter(|c| c.chunk_type().to_string() == chunk_type) {
+            shares.extend(Share::detect(chunk.data())?);
+        }
+    }
+    if shares.is_empty() {
+        return Err(Error::from("No shares found"));
+    }
+    combine(&shares)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_any_threshold_shares_recover_the_secret() {
+        let secret = b"launch code 0000";
+        let shares = split(secret, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+        for a in 0..5 {
+            for b in a + 1..5 {
+                for c in b + 1..5 {
+                    let subset = [shares[c].clone(), shares[a].clone(), shares[b].clone()];
+                    assert_eq!(combine(&subset).unwrap(), secret);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_too_few_shares() {
+        let shares = split(b"secret", 3, 5).unwrap();
+        let error = combine(&[shares[0].clone(), shares[4].clone(), shares[0].clone()]).unwrap_err();
+        assert_eq!(error.to_string(), "Need 3 of 5 shares to recover the message, only 2 provided");
+        assert!(split(b"secret", 4, 3).is_err());
+        assert!(split(b"secret", 0, 3).is_err());
+    }
+
+    #[test]
+    fn test_shares_from_different_sets() {
+        let first = split(b"secret", 2, 2).unwrap();
+        let second = split(b"secret", 2, 2).unwrap();
+        assert!(combine(&[first[0].clone(), second[1].clone()]).is_err());
+    }
+
+    #[test]
+    fn test_zero_threshold_share() {
+        let mut shares = split(b"secret", 2, 3).unwrap();
+        for share in &mut shares {
+            share.info.threshold = 0;
+        }
+        assert!(combine(&shares).is_err());
+    }
+
+    #[test]
+    fn test_shares_that_disagree() {
+        let shares = split(b"secret", 2, 3).unwrap();
+        let mut threshold = shares[1].clone();
+        threshold.info.threshold = 3;
+        assert!(combine(&[shares[0].clone(), threshold]).is_err());
+
+        let mut count = shares[1].clone();
+        count.info.count = 4;
+        assert!(combine(&[shares[0].clone(), count]).is_err());
+
+        let mut length = shares[1].clone();
+        length.data.push(0);
+        assert!(combine(&[shares[0].clone(), length]).is_err());
+
+        let mut conflicting = shares[0].clone();
+        conflicting.data[0] ^= 1;
+        assert!(combine(&[shares[0].clone(), conflicting, shares[1].clone()]).is_err());
+    }
+
+    #[test]
+    fn test_embed_and_extract_across_images() {
+        let chunk = |t: &str| Chunk::new(ChunkType::from_str(t).unwrap(), Vec::new());
+        let image = || Png::from_chunks(vec![chunk("IHDR"), chunk("IDAT"), chunk("IEND")]);
+        let mut pngs = vec![image(), image(), image(), image()];
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+
+        embed_across(&mut pngs, &chunk_type, b"two of four", 2).unwrap();
+        let share = Share::detect(pngs[1].chunk_by_type("ruSt").unwrap().data()).unwrap().unwrap();
+        assert_eq!(share.info.index, 2);
+        assert_eq!((share.info.threshold, share.info.count), (2, 4));
+
+        assert_eq!(extract_across(&pngs[2..], "ruSt").unwrap(), b"two of four");
+        assert!(extract_across(&pngs[..1], "ruSt").is_err());
+    }
+}
@@ -0,0 +1,446 @@
+use std::fmt;
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, Verifier};
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::envelope::Envelope;
+use crate::png::Png;
+use crate::{Error, Result};
+
+/// Type of the chunk holding the signature. Unsafe to copy, since editors
+/// that change the image invalidate it.
+pub const SIGNATURE_CHUNK: &str = "siGN";
+pub const KEY_SIZE: usize = 32;
+pub const DIGEST_SIZE: usize = 32;
+pub const SIGNATURE_SIZE: usize = 64;
+
+const SIGNING_PREFIX: &str = "pcm-signing:";
+const VERIFYING_PREFIX: &str = "pcm-verifying:";
+const DOMAIN: &[u8] = b"png-chunk-msg signature v1";
+// 类型(4) + 序号(2) + 摘要
+const ENTRY_SIZE: usize = 4 + 2 + DIGEST_SIZE;
+
+/// An Ed25519 key used to sign images.
+pub struct SigningKey {
+    key: ed25519_dalek::SigningKey,
+}
+
+/// The public half of a [`SigningKey`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyingKey {
+    key: ed25519_dalek::VerifyingKey,
+}
+
+impl SigningKey {
+    pub fn generate() -> SigningKey {
+        SigningKey {
+            key: ed25519_dalek::SigningKey::generate(&mut OsRng),
+        }
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        VerifyingKey {
+            key: self.key.verifying_key(),
+        }
+    }
+}
+
+impl fmt::Display for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", SIGNING_PREFIX, BASE64.encode(self.key.as_bytes()))
+    }
+}
+
+impl FromStr for SigningKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes = decode_key(s, SIGNING_PREFIX)?;
+        Ok(SigningKey {
+            key: ed25519_dalek::SigningKey::from_bytes(&bytes),
+        })
+    }
+}
+
+impl fmt::Display for VerifyingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", VERIFYING_PREFIX, BASE64.encode(self.key.as_bytes()))
+    }
+}
+
+impl FromStr for VerifyingKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes = decode_key(s, VERIFYING_PREFIX)?;
+        Ok(VerifyingKey {
+            key: ed25519_dalek::VerifyingKey::from_bytes(&bytes)?,
+        })
+    }
+}
+
+fn decode_key(s: &str, prefix: &str) -> Result<[u8; KEY_SIZE]> {
+    let encoded = s
+        .trim()
+        .strip_prefix(prefix)
+        .ok_or_else(|| Error::from(format!("Key must start with {}", prefix)))?;
+    let bytes = BASE64.decode(encoded)?;
+    bytes
+        .as_slice()
+        .try_into()
+        .map_err(|_| Error::from("Key must be 32 bytes long"))
+}
+
+/// State of a covered chunk when the signature is checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkStatus {
+    Intact,
+    Altered,
+    Missing,
+}
+
+/// One chunk listed in a signature. `occurrence` counts chunks of the same
+/// type, so the second IDAT has occurrence 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoveredChunk {
+    pub chunk_type: ChunkType,
+    pub occurrence: u16,
+    pub status: ChunkStatus,
+}
+
+impl fmt::Display for CoveredChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self.status {
+            ChunkStatus::Intact => "intact",
+            ChunkStatus::Altered => "altered",
+            ChunkStatus::Missing => "missing",
+        };
+        write!(f, "{} #{}: {}", self.chunk_type, self.occurrence, status)
+    }
+}
+
+/// Result of checking a signature whose own bytes are authentic.
+#[derive(Debug)]
+pub struct Verification {
+    pub signer: VerifyingKey,
+    pub covered: Vec<CoveredChunk>,
+    /// Chunks present in the image but not covered by the signature.
+    pub uncovered: Vec<(ChunkType, u16)>,
+}
+
+impl Verification {
+    /// Whether every covered chunk is still as it was when signed.
+    pub fn is_intact(&self) -> bool {
+        self.covered.iter().all(|c| c.status == ChunkStatus::Intact)
+    }
+}
+
+/// The chunk types signed when none are chosen: IHDR, IDAT and every
+/// private ancillary (message) chunk.
+pub fn default_selection(png: &Png) -> Vec<ChunkType> {
+    let mut types = vec![ChunkType::IHDR, ChunkType::IDAT];
+    for chunk in png.chunks() {
+        let chunk_type = chunk.chunk_type();
+        if !chunk_type.is_critical()
+            && !chunk_type.is_public()
+            && chunk_type.to_string() != SIGNATURE_CHUNK
+            && !types.contains(chunk_type)
+        {
+            types.push(chunk_type.clone());
+        }
+    }
+    types
+}
+
+/// Signs every chunk of the given types and stores the signature in a
+/// [`SIGNATURE_CHUNK`], replacing any previous one. Message envelopes stored
+/// directly in those chunks are marked with [`Envelope::SIGNED`] first.
+///
+/// Layout: `public key | entry count (u16) | entries | signature`, where each
+/// entry is `chunk type | occurrence (u16) | SHA-256 of type and data`.
+pub fn sign(png: &mut Png, key: &SigningKey, types: &[ChunkType]) -> Result<()> {
+    while png.remove_first_chunk(SIGNATURE_CHUNK).is_ok() {}
+    mark_envelopes(png, types)?;
+
+    let mut entries = Vec::new();
+    for chunk_type in types {
+        let occurrences = occurrences(png, chunk_type);
+        if occurrences.is_empty() {
+            return Err(Error::from(format!("No {} chunk to sign", chunk_type)));
+        }
+        for (occurrence, chunk) in occurrences.into_iter().enumerate() {
+            entries.extend_from_slice(&chunk_type.bytes());
+            entries.extend_from_slice(&u16::try_from(occurrence)?.to_be_bytes());
+            entries.extend_from_slice(&digest(chunk));
+        }
+    }
+    let count = u16::try_from(entries.len() / ENTRY_SIZE).map_err(|_| "Too many chunks to sign")?;
+
+    let mut data = Vec::with_capacity(KEY_SIZE + 2 + entries.len() + SIGNATURE_SIZE);
+    data.extend_from_slice(key.key.verifying_key().as_bytes());
+    data.extend_from_slice(&count.to_be_bytes());
+    data.extend_from_slice(&entries);
+    let signature = key.key.sign(&signed_message(&data));
+    data.extend_from_slice(&signature.to_bytes());
+
+    png.append_chunk(Chunk::new(ChunkType::from_str(SIGNATURE_CHUNK)?, data));
+    Ok(())
+}
+
+// 分片或纠错帧中的信封不在块的开头，保持不变
+fn mark_envelopes(png: &mut Png, types: &[ChunkType]) -> Result<()> {
+    let mut chunks = Vec::with_capacity(png.chunks().len());
+    for chunk in png.chunks() {
+        match Envelope::detect(chunk.data()) {
+            Ok(Some(mut envelope)) if types.contains(chunk.chunk_type()) && !envelope.is_signed() => {
+                envelope.flags |= Envelope::SIGNED;
+                chunks.push(Chunk::new(chunk.chunk_type().clone(), envelope.to_bytes()?));
+            }
+            _ => chunks.push(chunk.clone()),
+        }
+    }
+    *png = Png::from_chunks(chunks);
+    Ok(())
+}
+
+/// Checks the signature of `png` and reports the state of every covered
+/// chunk. The signature lists every chunk of each signed type, so chunks of
+/// those types added later are reported as altered. Fails if there is no
+/// signature, the signature itself was forged or modified, or it was made by
+/// a key other than `trusted`.
+pub fn verify(png: &Png, trusted: Option<&VerifyingKey>) -> Result<Verification> {
+    let chunk = png.chunk_by_type(SIGNATURE_CHUNK).ok_or("PNG is not signed")?;
+    let data = chunk.data();
+    let header = KEY_SIZE + 2;
+    if data.len() < header + SIGNATURE_SIZE {
+        return Err(Error::from("Signature chunk is too short"));
+    }
+    let count = u16::from_be_bytes([data[KEY_SIZE], data[KEY_SIZE + 1]]) as usize;
+    let body = header + count * ENTRY_SIZE;
+    if data.len() != body + SIGNATURE_SIZE {
+        return Err(Error::from("Signature chunk has the wrong length"));
+    }
+
+    let signer = VerifyingKey {
+        key: ed25519_dalek::VerifyingKey::from_bytes(data[..KEY_SIZE].try_into()?)?,
+    };
+    if trusted.is_some_and(|trusted| *trusted != signer) {
+        return Err(Error::from(format!("Signed by an untrusted key: {}", signer)));
+    }
+    let signature = Signature::from_slice(&data[body..])?;
+    signer
+        .key
+        .verify(&signed_message(&data[..body]), &signature)
+        .map_err(|_| Error::from("Invalid signature"))?;
+
+    let mut covered = Vec::with_capacity(count);
+    for entry in data[header..body].chunks_exact(ENTRY_SIZE) {
+        let chunk_type = ChunkType::try_from(<[u8; 4]>::try_from(&entry[..4])?)?;
+        let occurrence = u16::from_be_bytes([entry[4], entry[5]]);
+        let status = match occurrences(png, &chunk_type).get(occurrence as usize) {
+            Some(chunk) if digest(chunk)[..] == entry[6..] => ChunkStatus::Intact,
+            Some(_) => ChunkStatus::Altered,
+            None => ChunkStatus::Missing,
+        };
+        covered.push(CoveredChunk {
+            chunk_type,
+            occurrence,
+            status,
+        });
+    }
+
+    // 签名列出了每种类型的全部块，之后多出来的同类型块也算被修改
+    let mut signed_types: Vec<ChunkType> = Vec::new();
+    for c in &covered {
+        if !signed_types.contains(&c.chunk_type) {
+            signed_types.push(c.chunk_type.clone());
+        }
+    }
+    for chunk_type in signed_types {
+        let signed = covered.iter().filter(|c| c.chunk_type == chunk_type).count();
+        for occurrence in signed..occurrences(png, &chunk_type).len() {
+            covered.push(CoveredChunk {
+                chunk_type: chunk_type.clone(),
+                occurrence: u16::try_from(occurrence)?,
+                status: ChunkStatus::Altered,
+            });
+        }
+    }
+
+    let mut uncovered = Vec::new();
+    let mut seen: Vec<(ChunkType, u16)> = Vec::new();
+    for chunk in png.chunks() {
+        let chunk_type = chunk.chunk_type();
+        let occurrence = seen.iter().filter(|(t, _)| t == chunk_type).count() as u16;
+        seen.push((chunk_type.clone(), occurrence));
+        let is_covered = covered
+            .iter()
+            .any(|c| c.chunk_type == *chunk_type && c.occurrence == occurrence);
+        if !is_covered && chunk_type.to_string() != SIGNATURE_CHUNK {
+            uncovered.push((chunk_type.clone(), occurrence));
+        }
+    }
+
+    Ok(Verification {
+        signer,
+        covered,
+        uncovered,
+    })
+}
+
+fn occurrences<'a>(png: &'a Png, chunk_type: &ChunkType) -> Vec<&'a Chunk> {
+    png.chunks().iter().filter(|c| c.chunk_type() == chunk_type).collect()
+}
+
+fn digest(chunk: &Chunk) -> [u8; DIGEST_SIZE] {
+    let mut hasher = Sha256::new();
+    hasher.update(chunk.chunk_type().bytes());
+    hasher.update(chunk.data());
+    hasher.finalize().into()
+}
+
+fn signed_message(body: &[u8]) -> Vec<u8> {
+    let mut message = DOMAIN.to_vec();
+    message.extend_from_slice(body);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::{Compression, Decryption, Encryption, Message};
+    use crate::recipient::Identity;
+
+    fn testing_png() -> Png {
+        let chunk = |t: &str, data: &[u8]| Chunk::new(ChunkType::from_str(t).unwrap(), data.to_vec());
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk("tEXt", b"Comment\0hello"),
+            chunk("IDAT", b"first"),
+            chunk("IDAT", b"second"),
+            chunk("ruSt", b"secret message"),
+            chunk("IEND", &[]),
+        ])
+    }
+
+    fn statuses(verification: &Verification) -> Vec<String> {
+        verification.covered.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = SigningKey::generate();
+        let mut png = testing_png();
+        let types = default_selection(&png);
+        sign(&mut png, &key, &types).unwrap();
+
+        let verification = verify(&png, Some(&key.verifying_key())).unwrap();
+        assert!(verification.is_intact());
+        assert_eq!(
+            statuses(&verification),
+            vec!["IHDR #0: intact", "IDAT #0: intact", "IDAT #1: intact", "ruSt #0: intact"]
+        );
+        let uncovered: Vec<String> = verification.uncovered.iter().map(|(t, _)| t.to_string()).collect();
+        assert_eq!(uncovered, vec!["tEXt", "IEND"]);
+    }
+
+    #[test]
+    fn test_sign_marks_envelopes() {
+        let identity = Identity::generate();
+        let encryption = Encryption::Recipients(vec![identity.recipient()]);
+        let sealed = Message::text("signed and sealed").seal(Compression::None, Some(&encryption)).unwrap();
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(ChunkType::from_str("enVl").unwrap(), sealed));
+        let key = SigningKey::generate();
+        let types = default_selection(&png);
+        sign(&mut png, &key, &types).unwrap();
+
+        let data = png.chunk_by_type("enVl").unwrap().data();
+        assert!(Envelope::detect(data).unwrap().unwrap().is_signed());
+        assert!(verify(&png, Some(&key.verifying_key())).unwrap().is_intact());
+        let opened = Message::open(data, Some(&Decryption::Identity(identity))).unwrap();
+        assert_eq!(opened.data, b"signed and sealed");
+    }
+
+    #[test]
+    fn test_verify_reports_altered_chunks() {
+        let key = SigningKey::generate();
+        let mut png = testing_png();
+        let types = default_selection(&png);
+        sign(&mut png, &key, &types).unwrap();
+
+        png.remove_first_chunk("ruSt").unwrap();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"forged".to_vec()));
+        png.remove_first_chunk("IDAT").unwrap();
+
+        let verification = verify(&png, None).unwrap();
+        assert!(!verification.is_intact());
+        assert_eq!(
+            statuses(&verification),
+            vec!["IHDR #0: intact", "IDAT #0: altered", "IDAT #1: missing", "ruSt #0: altered"]
+        );
+    }
+
+    #[test]
+    fn test_verify_reports_added_chunks() {
+        let key = SigningKey::generate();
+        let mut png = testing_png();
+        let types = default_selection(&png);
+        sign(&mut png, &key, &types).unwrap();
+
+        png.append_chunk(Chunk::new(ChunkType::IDAT, b"third".to_vec()));
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"another message".to_vec()));
+        let verification = verify(&png, None).unwrap();
+        assert!(!verification.is_intact());
+        assert_eq!(
+            statuses(&verification),
+            vec![
+                "IHDR #0: intact",
+                "IDAT #0: intact",
+                "IDAT #1: intact",
+                "ruSt #0: intact",
+                "IDAT #2: altered",
+                "ruSt #1: altered",
+            ]
+        );
+        assert!(verification.uncovered.iter().all(|(t, _)| t.is_public()));
+    }
+
+    #[test]
+    fn test_verify_rejects_forged_signature() {
+        let key = SigningKey::generate();
+        let mut png = testing_png();
+        sign(&mut png, &key, &[ChunkType::from_str("IHDR").unwrap()]).unwrap();
+        assert!(verify(&png, Some(&SigningKey::generate().verifying_key())).is_err());
+
+        let chunk = png.remove_first_chunk(SIGNATURE_CHUNK).unwrap();
+        let mut data = chunk.data().to_vec();
+        data[KEY_SIZE + 2] ^= 0x20;
+        png.append_chunk(Chunk::new(chunk.chunk_type().clone(), data));
+        assert!(verify(&png, None).is_err());
+    }
+
+    #[test]
+    fn test_sign_missing_chunk_type() {
+        let key = SigningKey::generate();
+        let mut png = testing_png();
+        assert!(sign(&mut png, &key, &[ChunkType::from_str("zzZz").unwrap()]).is_err());
+        assert!(verify(&png, None).is_err());
+    }
+
+    #[test]
+    fn test_key_strings_round_trip() {
+        let key = SigningKey::generate();
+        let parsed = SigningKey::from_str(&key.to_string()).unwrap();
+        assert_eq!(parsed.verifying_key(), key.verifying_key());
+        let public = key.verifying_key().to_string();
+        assert_eq!(VerifyingKey::from_str(&public).unwrap(), key.verifying_key());
+        assert!(VerifyingKey::from_str(&key.to_string()).is_err());
+    }
+}
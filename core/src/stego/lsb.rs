@@ -0,0 +1,357 @@
+use std::str::FromStr;
+
+use rand_chacha::rand_core::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+use crate::chunk::Chunk;
+use crate::encrypt;
+use crate::idat::{FilterStrategy, Raster};
+use crate::ihdr::{ColorType, Ihdr};
+use crate::png::Png;
+use crate::stego::{from_bits, to_bits};
+use crate::{Error, Result};
+
+/// Size of the big-endian payload length, stored three times in front of
+/// the payload.
+pub const LENGTH_SIZE: usize = 4;
+// 按位多数表决。长度在纠错帧之外，损坏一位就会读错整个载荷
+const LENGTH_COPIES: usize = 3;
+const HEADER_SIZE: usize = LENGTH_SIZE * LENGTH_COPIES;
+const ORDER_SALT: &[u8] = b"png-chunk-msg lsb order";
+
+/// Which colour channels carry payload bits. For grayscale images any of
+/// red, green or blue selects the gray channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelMask {
+    pub red: bool,
+    pub green: bool,
+    pub blue: bool,
+    pub alpha: bool,
+}
+
+impl ChannelMask {
+    pub const RGB: ChannelMask = ChannelMask {
+        red: true,
+        green: true,
+        blue: true,
+        alpha: false,
+    };
+
+    /// Indices of the selected channels within a pixel of `color_type`.
+    pub fn channels(&self, color_type: ColorType) -> Vec<usize> {
+        let gray = self.red || self.green || self.blue;
+        let selected: Vec<(bool, usize)> = match color_type {
+            ColorType::Grayscale => vec![(gray, 0)],
+            ColorType::GrayscaleAlpha => vec![(gray, 0), (self.alpha, 1)],
+            ColorType::Rgb => vec![(self.red, 0), (self.green, 1), (self.blue, 2)],
+            ColorType::Rgba => vec![(self.red, 0), (self.green, 1), (self.blue, 2), (self.alpha, 3)],
+            ColorType::Indexed => Vec::new(),
+        };
+        selected.into_iter().filter(|(on, _)| *on).map(|(_, c)| c).collect()
+    }
+}
+
+impl FromStr for ChannelMask {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.is_empty() {
+            return Err(Error::from("At least one channel is required"));
+        }
+        let mut mask = ChannelMask {
+            red: false,
+            green: false,
+            blue: false,
+            alpha: false,
+        };
+        for c in s.chars() {
+            match c.to_ascii_lowercase() {
+                'r' => mask.red = true,
+                'g' => mask.green = true,
+                'b' => mask.blue = true,
+                'a' => mask.alpha = true,
+                _ => return Err(Error::from("Channels must be a combination of r, g, b and a")),
+            }
+        }
+        Ok(mask)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LsbOptions {
+    /// How many low bits of each selected sample are replaced (1-8).
+    pub bits_per_channel: u8,
+    pub channels: ChannelMask,
+    /// Passphrase that scatters the payload over a pseudorandom pixel order.
+    /// Without it pixels are used in scanline order. The order is derived
+    /// with Argon2id to slow down guessing, but it only hides where the bits
+    /// are and is no substitute for encrypting the payload.
+    pub key: Option<String>,
+}
+
+impl Default for LsbOptions {
+    fn default() -> Self {
+        LsbOptions {
+            bits_per_channel: 1,
+            channels: ChannelMask::RGB,
+            key: None,
+        }
+    }
+}
+
+/// Number of payload bytes `png` can carry with `options`.
+pub fn capacity(png: &Png, options: &LsbOptions) -> Result<usize> {
+    let ihdr = png.ihdr()?;
+    // 顺序不影响容量，省掉 key 的派生
+    let unordered = LsbOptions { key: None, ..options.clone() };
+    let bits = sample_positions(&ihdr, &unordered)?.len() * options.bits_per_channel as usize;
+    Ok((bits / 8).saturating_sub(HEADER_SIZE))
+}
+
+/// Writes `payload` into the low bits of the image samples and re-encodes
+/// IDAT. The image is de-interlaced in the process. Unknown unsafe-to-copy
+/// chunks no longer match the new image data, so they are removed and
+/// returned.
+pub fn embed(png: &mut Png, payload: &[u8], options: &LsbOptions) -> Result<Vec<Chunk>> {
+    let mut raster = Raster::decode(png)?;
+    let positions = sample_positions(raster.ihdr(), options)?;
+    let available = positions.len() * options.bits_per_channel as usize / 8;
+    if payload.len() + HEADER_SIZE > available {
+        return Err(Error::from(format!(
+            "Payload of {} bytes exceeds the LSB capacity of {} bytes",
+            payload.len(),
+            available.saturating_sub(HEADER_SIZE)
+        )));
+    }
+
+    let length = u32::try_from(payload.len())?.to_be_bytes().repeat(LENGTH_COPIES);
+    let mut bits = to_bits(&length).chain(to_bits(payload));
+    'samples: for &(x, y, channel) in &positions {
+        let mut value = raster.sample(x, y, channel);
+        for i in (0..options.bits_per_channel).rev() {
+            match bits.next() {
+                Some(bit) => value = (value & !(1 << i)) | ((bit as u16) << i),
+                None => {
+                    raster.set_sample(x, y, channel, value);
+                    break 'samples;
+                }
+            }
+        }
+        raster.set_sample(x, y, channel, value);
+    }
+
+    let data = raster.encode(FilterStrategy::Adaptive, 9)?;
+    png.replace_image_data(raster.ihdr(), &data);
+    Ok(png.remove_unsafe_to_copy())
+}
+
+/// Reads back a payload written by [`embed`] with the same options.
+pub fn extract(png: &Png, options: &LsbOptions) -> Result<Vec<u8>> {
+    let raster = Raster::decode(png)?;
+    let positions = sample_positions(raster.ihdr(), options)?;
+    let mut bits = positions.iter().flat_map(|&(x, y, channel)| {
+        let value = raster.sample(x, y, channel);
+        (0..options.bits_per_channel).rev().map(move |i| (value >> i) & 1 == 1)
+    });
+
+    let copies = from_bits(bits.by_ref().take(HEADER_SIZE * 8));
+    if copies.len() < HEADER_SIZE {
+        return Err(Error::from("No LSB payload found"));
+    }
+    let (a, b, c) = (&copies[..LENGTH_SIZE], &copies[LENGTH_SIZE..2 * LENGTH_SIZE], &copies[2 * LENGTH_SIZE..]);
+    let length: Vec<u8> = (0..LENGTH_SIZE).map(|i| (a[i] & b[i]) | (a[i] & c[i]) | (b[i] & c[i])).collect();
+    let length = u32::from_be_bytes(length.as_slice().try_into()?) as usize;
+    let available = positions.len() * options.bits_per_channel as usize / 8;
+    if length + HEADER_SIZE > available {
+        return Err(Error::from("No LSB payload found"));
+    }
+    Ok(from_bits(bits.take(length * 8)))
+}
+
+// 列出所有用于隐写的 (x, y, channel)，有 key 时像素顺序被打乱
+pub(crate) fn sample_positions(ihdr: &Ihdr, options: &LsbOptions) -> Result<Vec<(u32, u32, usize)>> {
+    if ihdr.color_type == ColorType::Indexed {
+        return Err(Error::from("LSB embedding does not support indexed images"));
+    }
+    if ihdr.bit_depth < 8 {
+        return Err(Error::from("LSB embedding requires a bit depth of 8 or 16"));
+    }
+    if options.bits_per_channel == 0 || options.bits_per_channel > 8 {
+        return Err(Error::from("Bits per channel must be between 1 and 8"));
+    }
+    let channels = options.channels.channels(ihdr.color_type);
+    if channels.is_empty() {
+        return Err(Error::from("None of the selected channels exist in this image"));
+    }
+
+    let mut pixels: Vec<(u32, u32)> = (0..ihdr.height)
+        .flat_map(|y| (0..ihdr.width).map(move |x| (x, y)))
+        .collect();
+    if let Some(key) = &options.key {
+        shuffle(&mut pixels, key)?;
+    }
+    Ok(pixels
+        .into_iter()
+        .flat_map(|(x, y)| channels.iter().map(move |&c| (x, y, c)))
+        .collect())
+}
+
+// Fisher-Yates 洗牌。自己实现而不用 rand 的 shuffle，保证不同版本间顺序不变
+fn shuffle<T>(items: &mut [T], key: &str) -> Result<()> {
+    // 固定的盐只用于区分用途，慢速的 KDF 才是防止暴力猜测的部分
+    let seed = encrypt::derive_key(key, ORDER_SALT)?;
+    let mut rng = ChaCha20Rng::from_seed(*seed);
+    for i in (1..items.len()).rev() {
+        let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::ecc;
+
+    fn testing_png(color_type: ColorType) -> Png {
+        let ihdr = Ihdr {
+            width: 16,
+            height: 8,
+            bit_depth: 8,
+            color_type,
+            interlaced: false,
+        };
+        let data = (0..ihdr.stride() * 8).map(|i| (i * 7) as u8).collect();
+        let raster = Raster::new(ihdr, data).unwrap();
+        Png::from_chunks(vec![
+            ihdr.to_chunk(),
+            Chunk::new(
+                ChunkType::from_str("IDAT").unwrap(),
+                raster.encode(FilterStrategy::Adaptive, 6).unwrap(),
+            ),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ])
+    }
+
+    #[test]
+    fn test_lsb_round_trip() {
+        let mut png = testing_png(ColorType::Rgb);
+        let options = LsbOptions::default();
+        embed(&mut png, b"hidden in plain sight", &options).unwrap();
+        assert_eq!(extract(&png, &options).unwrap(), b"hidden in plain sight");
+    }
+
+    #[test]
+    fn test_lsb_drops_unsafe_to_copy_chunks() {
+        let mut png = testing_png(ColorType::Rgb);
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruST").unwrap(), Vec::new()));
+        let dropped = embed(&mut png, b"x", &LsbOptions::default()).unwrap();
+        assert_eq!(dropped.len(), 1);
+        assert!(png.chunk_by_type("ruST").is_none());
+    }
+
+    #[test]
+    fn test_lsb_only_touches_low_bits() {
+        let original = testing_png(ColorType::Rgba);
+        let mut png = testing_png(ColorType::Rgba);
+        let options = LsbOptions {
+            bits_per_channel: 2,
+            channels: ChannelMask::from_str("ga").unwrap(),
+            key: None,
+        };
+        embed(&mut png, &[0xff; 40], &options).unwrap();
+
+        let before = Raster::decode(&original).unwrap();
+        let after = Raster::decode(&png).unwrap();
+        for (a, b) in before.data().iter().zip(after.data()) {
+            assert_eq!(a >> 2, b >> 2);
+        }
+        // 红色通道未被选中，应保持不变
+        assert_eq!(before.sample(3, 0, 0), after.sample(3, 0, 0));
+        assert_eq!(extract(&png, &options).unwrap(), vec![0xff; 40]);
+    }
+
+    #[test]
+    fn test_lsb_with_key() {
+        let mut png = testing_png(ColorType::Rgb);
+        let options = LsbOptions {
+            key: Some("correct horse".to_string()),
+            ..LsbOptions::default()
+        };
+        embed(&mut png, b"scattered", &options).unwrap();
+        assert_eq!(extract(&png, &options).unwrap(), b"scattered");
+
+        let wrong = LsbOptions {
+            key: Some("battery staple".to_string()),
+            ..LsbOptions::default()
+        };
+        assert_ne!(extract(&png, &wrong).ok().as_deref(), Some(&b"scattered"[..]));
+        assert_ne!(extract(&png, &LsbOptions::default()).ok().as_deref(), Some(&b"scattered"[..]));
+    }
+
+    #[test]
+    fn test_damaged_pixels_with_error_correction() {
+        let mut png = testing_png(ColorType::Rgba);
+        let options = LsbOptions {
+            bits_per_channel: 8,
+            ..LsbOptions::default()
+        };
+        let frame = ecc::encode(b"survives", 20).unwrap();
+        embed(&mut png, &frame, &options).unwrap();
+
+        // 损坏第三份长度头和载荷中的一些字节
+        let mut raster = Raster::decode(&png).unwrap();
+        for &(x, y, channel) in &sample_positions(raster.ihdr(), &options).unwrap()[..] {
+            if (x + y * 16) % 29 == 3 {
+                let value = raster.sample(x, y, channel);
+                raster.set_sample(x, y, channel, value ^ 0b0100);
+            }
+        }
+        png.replace_image_data(raster.ihdr(), &raster.encode(FilterStrategy::Adaptive, 6).unwrap());
+
+        let damaged = extract(&png, &options).unwrap();
+        assert_ne!(damaged, frame);
+        let corrected = ecc::detect(&damaged).unwrap().unwrap();
+        assert_eq!(corrected.data, b"survives");
+        assert!(corrected.errors > 0);
+    }
+
+    #[test]
+    fn test_key_order_is_a_permutation() {
+        let ihdr = testing_png(ColorType::Rgb).ihdr().unwrap();
+        let options = LsbOptions {
+            key: Some("key".to_string()),
+            ..LsbOptions::default()
+        };
+        let mut shuffled = sample_positions(&ihdr, &options).unwrap();
+        let ordered = sample_positions(&ihdr, &LsbOptions::default()).unwrap();
+        assert_ne!(shuffled, ordered);
+        assert_eq!(shuffled, sample_positions(&ihdr, &options).unwrap());
+        shuffled.sort();
+        let mut ordered = ordered;
+        ordered.sort();
+        assert_eq!(shuffled, ordered);
+    }
+
+    #[test]
+    fn test_lsb_capacity() {
+        let png = testing_png(ColorType::Rgb);
+        let options = LsbOptions::default();
+        // 16 * 8 像素 * 3 通道 * 1 位 = 48 字节，减去三份长度头
+        assert_eq!(capacity(&png, &options).unwrap(), 36);
+
+        let mut png = testing_png(ColorType::Rgb);
+        assert!(embed(&mut png, &[0; 37], &options).is_err());
+        assert!(embed(&mut png, &[0; 36], &options).is_ok());
+    }
+
+    #[test]
+    fn test_channel_mask_from_str() {
+        let mask = ChannelMask::from_str("rA").unwrap();
+        assert_eq!(mask.channels(ColorType::Rgba), vec![0, 3]);
+        assert_eq!(mask.channels(ColorType::GrayscaleAlpha), vec![0, 1]);
+        assert!(ChannelMask::from_str("rx").is_err());
+        assert!(ChannelMask::from_str("").is_err());
+    }
+}
@@ -0,0 +1,36 @@
+//! Ways of hiding a payload inside the image itself rather than in a chunk of
+//! its own.
+
+pub mod lsb;
+pub mod palette;
+pub mod text;
+pub mod timing;
+pub mod zlib_slack;
+
+// 所有隐写方式都按字节的高位在前逐位写入
+pub(crate) fn to_bits(bytes: &[u8]) -> impl Iterator<Item = bool> + '_ {
+    bytes
+        .iter()
+        .flat_map(|&byte| (0..8).rev().map(move |i| (byte >> i) & 1 == 1))
+}
+
+pub(crate) fn from_bits(bits: impl IntoIterator<Item = bool>) -> Vec<u8> {
+    let bits: Vec<bool> = bits.into_iter().collect();
+    bits.chunks(8)
+        .map(|byte| byte.iter().fold(0u8, |acc, &bit| (acc << 1) | bit as u8))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bits_round_trip() {
+        let bytes = b"bits";
+        let bits: Vec<bool> = to_bits(bytes).collect();
+        assert_eq!(bits.len(), 32);
+        assert_eq!(&bits[..8], &[false, true, true, false, false, false, true, false]);
+        assert_eq!(from_bits(bits), bytes);
+    }
+}
@@ -0,0 +1,325 @@
+use rand::RngCore;
+
+use crate::chunk::Chunk;
+use crate::idat::{FilterStrategy, Raster};
+use crate::ihdr::ColorType;
+use crate::palette::{Palette, PaletteEntry};
+use crate::png::Png;
+use crate::{Error, Result};
+
+/// Size of the big-endian payload length stored in front of the payload.
+pub const LENGTH_SIZE: usize = 2;
+
+/// Number of payload bytes the palette order of `png` can carry. Duplicate
+/// palette entries are merged before counting.
+pub fn capacity(png: &Png) -> Result<usize> {
+    let palette = indexed_palette(png)?;
+    let (unique, _) = dedupe(&palette);
+    Ok(width(unique.len()).saturating_sub(LENGTH_SIZE))
+}
+
+/// Encodes `payload` as the order of the PLTE entries. Pixel indices are
+/// remapped so the rendered image stays identical. Unknown unsafe-to-copy
+/// chunks are removed, since PLTE and IDAT change, and returned.
+pub fn embed(png: &mut Png, payload: &[u8]) -> Result<Vec<Chunk>> {
+    let palette = indexed_palette(png)?;
+    let (unique, merged) = dedupe(&palette);
+    let width = width(unique.len());
+    if payload.len() + LENGTH_SIZE > width {
+        return Err(Error::from(format!(
+            "Payload of {} bytes exceeds the palette capacity of {} bytes",
+            payload.len(),
+            width.saturating_sub(LENGTH_SIZE)
+        )));
+    }
+
+    // 未使用的部分用随机字节填充，否则排列的末尾会呈现出规律
+    let mut framed = vec![0; width];
+    framed[..LENGTH_SIZE].copy_from_slice(&u16::try_from(payload.len())?.to_be_bytes());
+    framed[LENGTH_SIZE..LENGTH_SIZE + payload.len()].copy_from_slice(payload);
+    rand::thread_rng().fill_bytes(&mut framed[LENGTH_SIZE + payload.len()..]);
+
+    let canonical = canonical(&unique);
+    let mut number = BigUint::from_be_bytes(&framed);
+    let mut available: Vec<usize> = (0..canonical.len()).collect();
+    let mut order = Vec::with_capacity(canonical.len());
+    for radix in (1..=canonical.len() as u32).rev() {
+        let digit = number.div_rem(radix);
+        order.push(available.remove(digit as usize));
+    }
+
+    // order[新位置] = canonical 中的位置
+    let entries: Vec<PaletteEntry> = order.iter().map(|&i| unique[canonical[i]]).collect();
+    let mut new_index = vec![0; unique.len()];
+    for (position, &i) in order.iter().enumerate() {
+        new_index[canonical[i]] = position;
+    }
+    let remap: Vec<usize> = merged.iter().map(|&u| new_index[u]).collect();
+    rewrite(png, Palette::new(entries)?, &remap)?;
+    Ok(png.remove_unsafe_to_copy())
+}
+
+/// Reads back a payload written by [`embed`].
+pub fn extract(png: &Png) -> Result<Vec<u8>> {
+    let palette = indexed_palette(png)?;
+    let (unique, _) = dedupe(&palette);
+    let canonical = canonical(&unique);
+    let width = width(unique.len());
+
+    let mut available: Vec<PaletteEntry> = canonical.iter().map(|&i| unique[i]).collect();
+    let mut digits = Vec::with_capacity(unique.len());
+    for entry in &unique {
+        let digit = available.iter().position(|e| e == entry).unwrap();
+        available.remove(digit);
+        digits.push(digit as u32);
+    }
+    let mut number = BigUint::default();
+    for (i, &digit) in digits.iter().enumerate().rev() {
+        number.mul_add((unique.len() - i) as u32, digit);
+    }
+
+    let framed = number.to_be_bytes(width).ok_or("No palette payload found")?;
+    if framed.len() < LENGTH_SIZE {
+        return Err(Error::from("No palette payload found"));
+    }
+    let length = u16::from_be_bytes([framed[0], framed[1]]) as usize;
+    if length + LENGTH_SIZE > width {
+        return Err(Error::from("No palette payload found"));
+    }
+    Ok(framed[LENGTH_SIZE..LENGTH_SIZE + length].to_vec())
+}
+
+fn indexed_palette(png: &Png) -> Result<Palette> {
+    // 重新排列调色板会改变所有帧的颜色，而这里只重映射默认图像
+    if png.chunk_by_type("acTL").is_some() {
+        return Err(Error::from("Palette embedding does not support animated PNGs"));
+    }
+    if png.ihdr()?.color_type != ColorType::Indexed {
+        return Err(Error::from("Palette embedding requires an indexed image"));
+    }
+    png.palette()?.ok_or_else(|| Error::from("Indexed image without PLTE"))
+}
+
+// 合并重复条目，返回去重后的条目以及 原索引 -> 去重后索引 的映射
+fn dedupe(palette: &Palette) -> (Vec<PaletteEntry>, Vec<usize>) {
+    let mut unique: Vec<PaletteEntry> = Vec::new();
+    let merged = palette
+        .entries()
+        .iter()
+        .map(|entry| match unique.iter().position(|u| u == entry) {
+            Some(i) => i,
+            None => {
+                unique.push(*entry);
+                unique.len() - 1
+            }
+        })
+        .collect();
+    (unique, merged)
+}
+
+// 规范顺序：按 (r, g, b, a) 排序后的索引
+fn canonical(entries: &[PaletteEntry]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..entries.len()).collect();
+    order.sort_by_key(|&i| {
+        let e = entries[i];
+        (e.r, e.g, e.b, e.a)
+    });
+    order
+}
+
+// n 个条目的排列最多能表示 floor(log2(n!)) 位
+fn width(entries: usize) -> usize {
+    let mut factorial = BigUint::from_be_bytes(&[1]);
+    for k in 2..=entries as u32 {
+        factorial.mul_add(k, 0);
+    }
+    factorial.bits().saturating_sub(1) / 8
+}
+
+fn rewrite(png: &mut Png, palette: Palette, remap: &[usize]) -> Result<()> {
+    let mut raster = Raster::decode(png)?;
+    for y in 0..raster.height() {
+        for x in 0..raster.width() {
+            let index = raster.sample(x, y, 0) as usize;
+            let new = *remap.get(index).ok_or("Palette index out of range")?;
+            raster.set_sample(x, y, 0, new as u16);
+        }
+    }
+    let data = raster.encode(FilterStrategy::Adaptive, 9)?;
+    png.replace_image_data(raster.ihdr(), &data);
+
+    // bKGD 保存的是调色板索引，也需要重新映射
+    if let Some(bkgd) = png.chunk_by_type("bKGD") {
+        if let Some(&index) = bkgd.data().first() {
+            let new = *remap.get(index as usize).ok_or("bKGD index out of range")?;
+            let chunk = Chunk::new(bkgd.chunk_type().clone(), vec![new as u8]);
+            png.replace_chunk(chunk);
+        }
+    }
+    // hIST 是每个条目的使用频率，重新排列后已无意义
+    while png.remove_first_chunk("hIST").is_ok() {}
+    png.replace_palette(&palette);
+    Ok(())
+}
+
+/// Just enough arbitrary precision arithmetic for Lehmer codes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct BigUint {
+    // 小端存储的 32 位分量
+    limbs: Vec<u32>,
+}
+
+impl BigUint {
+    fn from_be_bytes(bytes: &[u8]) -> BigUint {
+        let mut number = BigUint::default();
+        for &byte in bytes {
+            number.mul_add(256, byte as u32);
+        }
+        number
+    }
+
+    fn to_be_bytes(&self, width: usize) -> Option<Vec<u8>> {
+        let mut number = self.clone();
+        let mut bytes = vec![0; width];
+        for byte in bytes.iter_mut().rev() {
+            *byte = number.div_rem(256) as u8;
+        }
+        number.limbs.is_empty().then_some(bytes)
+    }
+
+    fn mul_add(&mut self, factor: u32, addend: u32) {
+        let mut carry = addend as u64;
+        for limb in self.limbs.iter_mut() {
+            let value = *limb as u64 * factor as u64 + carry;
+            *limb = value as u32;
+            carry = value >> 32;
+        }
+        if carry > 0 {
+            self.limbs.push(carry as u32);
+        }
+    }
+
+    fn div_rem(&mut self, divisor: u32) -> u32 {
+        let mut remainder = 0u64;
+        for limb in self.limbs.iter_mut().rev() {
+            let value = (remainder << 32) | *limb as u64;
+            *limb = (value / divisor as u64) as u32;
+            remainder = value % divisor as u64;
+        }
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+        remainder as u32
+    }
+
+    fn bits(&self) -> usize {
+        match self.limbs.last() {
+            Some(&top) => (self.limbs.len() - 1) * 32 + (32 - top.leading_zeros() as usize),
+            None => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::ihdr::Ihdr;
+    use std::str::FromStr;
+
+    fn testing_png(colors: usize) -> Png {
+        let entries: Vec<PaletteEntry> = (0..colors)
+            .map(|i| PaletteEntry {
+                r: (i * 7) as u8,
+                g: (i * 13) as u8,
+                b: (255 - i) as u8,
+                a: if i == 3 { 0 } else { 255 },
+            })
+            .collect();
+        let palette = Palette::new(entries).unwrap();
+        let ihdr = Ihdr {
+            width: 20,
+            height: 10,
+            bit_depth: 8,
+            color_type: ColorType::Indexed,
+            interlaced: false,
+        };
+        let data = (0..200).map(|i| (i % colors) as u8).collect();
+        let raster = Raster::new(ihdr, data).unwrap();
+        let mut chunks = vec![ihdr.to_chunk(), palette.to_plte_chunk()];
+        chunks.extend(palette.to_trns_chunk());
+        chunks.push(Chunk::new(ChunkType::from_str("bKGD").unwrap(), vec![5]));
+        chunks.push(Chunk::new(
+            ChunkType::from_str("IDAT").unwrap(),
+            raster.encode(FilterStrategy::Adaptive, 6).unwrap(),
+        ));
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+        Png::from_chunks(chunks)
+    }
+
+    fn rendered(png: &Png) -> Vec<PaletteEntry> {
+        let raster = Raster::decode(png).unwrap();
+        let palette = png.palette().unwrap().unwrap();
+        (0..raster.height())
+            .flat_map(|y| (0..raster.width()).map(move |x| (x, y)))
+            .map(|(x, y)| palette.entries()[raster.sample(x, y, 0) as usize])
+            .collect()
+    }
+
+    #[test]
+    fn test_palette_round_trip() {
+        let mut png = testing_png(64);
+        let original = rendered(&png);
+        let background = png.palette().unwrap().unwrap().entries()[5];
+
+        embed(&mut png, b"order is information").unwrap();
+        assert_eq!(extract(&png).unwrap(), b"order is information");
+        assert_eq!(rendered(&png), original);
+
+        let index = png.chunk_by_type("bKGD").unwrap().data()[0] as usize;
+        assert_eq!(png.palette().unwrap().unwrap().entries()[index], background);
+    }
+
+    #[test]
+    fn test_palette_drops_unsafe_to_copy_chunks() {
+        let mut png = testing_png(16);
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruST").unwrap(), Vec::new()));
+        let dropped = embed(&mut png, b"x").unwrap();
+        assert_eq!(dropped.len(), 1);
+        assert!(png.chunk_by_type("ruST").is_none());
+    }
+
+    #[test]
+    fn test_palette_capacity() {
+        // log2(64!) ≈ 295.9 位，可用 36 字节，减去长度头
+        assert_eq!(capacity(&testing_png(64)).unwrap(), 34);
+
+        let mut png = testing_png(64);
+        assert!(embed(&mut png, &[1; 35]).is_err());
+        assert!(embed(&mut png, &[1; 34]).is_ok());
+        assert_eq!(extract(&png).unwrap(), vec![1; 34]);
+    }
+
+    #[test]
+    fn test_duplicate_entries_are_merged() {
+        let mut png = testing_png(16);
+        let mut entries = png.palette().unwrap().unwrap().entries().to_vec();
+        entries[15] = entries[0];
+        png.replace_palette(&Palette::new(entries).unwrap());
+        let original = rendered(&png);
+
+        // 15 个不同条目：log2(15!) ≈ 40.3 位，正好 3 字节负载
+        assert_eq!(capacity(&png).unwrap(), 3);
+        embed(&mut png, b"dup").unwrap();
+        assert_eq!(extract(&png).unwrap(), b"dup");
+        assert_eq!(rendered(&png), original);
+    }
+
+    #[test]
+    fn test_big_uint() {
+        let number = BigUint::from_be_bytes(&[1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(number.bits(), 65);
+        assert_eq!(number.to_be_bytes(9).unwrap(), vec![1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert!(number.to_be_bytes(8).is_none());
+    }
+}
@@ -0,0 +1,168 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::{Error, Result};
+
+/// Keyword used when none is given.
+pub const DEFAULT_KEYWORD: &str = "Comment";
+// 按 MIME 的习惯每 76 个字符换行，看起来更像普通的编码文本
+const LINE_LENGTH: usize = 76;
+// 更短的 base64 文本很可能只是普通单词
+const MIN_ENCODED_LENGTH: usize = 32;
+
+/// Where the payload is disguised.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextOptions {
+    /// Keyword of the text chunk, e.g. `Comment` or `Description`.
+    pub keyword: String,
+    /// Use an iTXt chunk instead of tEXt.
+    pub international: bool,
+}
+
+impl Default for TextOptions {
+    fn default() -> Self {
+        TextOptions {
+            keyword: DEFAULT_KEYWORD.to_string(),
+            international: false,
+        }
+    }
+}
+
+/// Stores `payload` base64 encoded in a standard tEXt or iTXt chunk, so the
+/// file shows nothing but an ordinary text comment to other tools.
+pub fn embed(png: &mut Png, payload: &[u8], options: &TextOptions) -> Result<()> {
+    let keyword = &options.keyword;
+    // 关键字为 1 到 79 个可打印的 Latin-1 字符，首尾不能是空格
+    let valid = (1..=79).contains(&keyword.len())
+        && keyword.bytes().all(|b| (32..=126).contains(&b))
+        && !keyword.starts_with(' ')
+        && !keyword.ends_with(' ');
+    if !valid {
+        return Err(Error::from("Keyword must be 1 to 79 printable characters"));
+    }
+
+    let encoded = BASE64.encode(payload);
+    let lines: Vec<&[u8]> = encoded.as_bytes().chunks(LINE_LENGTH).collect();
+    let mut data = keyword.as_bytes().to_vec();
+    data.push(0);
+    let chunk_type = if options.international {
+        // 不压缩，语言标签和翻译后的关键字留空
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        ChunkType::ITXT
+    } else {
+        ChunkType::TEXT
+    };
+    data.extend_from_slice(&lines.join(&b'\n'));
+    png.append_chunk(Chunk::new(chunk_type, data));
+    Ok(())
+}
+
+/// Reads the payload from the first tEXt or iTXt chunk with the keyword of
+/// `options` whose text is base64.
+pub fn extract(png: &Png, options: &TextOptions) -> Result<Vec<u8>> {
+    text_chunks(png)
+        .filter(|(keyword, _)| *keyword == options.keyword)
+        .find_map(|(_, text)| decode(&text))
+        .ok_or_else(|| Error::from(format!("No encoded text chunk with keyword {} found", options.keyword)))
+}
+
+/// Keywords and decoded sizes of text chunks that hold base64 data rather
+/// than prose.
+pub fn detect(png: &Png) -> Vec<(String, usize)> {
+    text_chunks(png)
+        .filter(|(_, text)| text.len() >= MIN_ENCODED_LENGTH)
+        .filter_map(|(keyword, text)| decode(&text).map(|data| (keyword, data.len())))
+        .collect()
+}
+
+fn decode(text: &str) -> Option<Vec<u8>> {
+    // 只去掉换行，带空格的普通文本不算
+    let compact: String = text.lines().collect();
+    BASE64.decode(compact).ok()
+}
+
+// 返回未压缩的 tEXt 和 iTXt 块的关键字和文本
+fn text_chunks(png: &Png) -> impl Iterator<Item = (String, String)> + '_ {
+    png.chunks().iter().filter_map(|chunk| {
+        let data = chunk.data();
+        let separator = data.iter().position(|&b| b == 0)?;
+        let keyword = String::from_utf8_lossy(&data[..separator]).into_owned();
+        let rest = &data[separator + 1..];
+        match &chunk.chunk_type().bytes() {
+            b"tEXt" => Some((keyword, String::from_utf8_lossy(rest).into_owned())),
+            b"iTXt" => {
+                let (&compressed, rest) = rest.split_first()?;
+                if compressed != 0 {
+                    return None;
+                }
+                // 跳过压缩方式、语言标签和翻译后的关键字
+                let mut fields = rest.get(1..)?.splitn(3, |&b| b == 0);
+                let text = fields.nth(2)?;
+                Some((keyword, String::from_utf8(text.to_vec()).ok()?))
+            }
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        let chunk = |t: &str, data: &[u8]| Chunk::new(ChunkType::from_str(t).unwrap(), data.to_vec());
+        Png::from_chunks(vec![
+            chunk("IHDR", &[]),
+            chunk("tEXt", b"Comment\0Made with love"),
+            chunk("IDAT", &[]),
+            chunk("IEND", &[]),
+        ])
+    }
+
+    fn payload() -> Vec<u8> {
+        (0..200).map(|i| (i * 13 % 256) as u8).collect()
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let mut png = testing_png();
+        embed(&mut png, &payload(), &TextOptions::default()).unwrap();
+        let chunk = &png.chunks()[3];
+        assert_eq!(chunk.chunk_type().to_string(), "tEXt");
+        assert!(chunk.data().starts_with(b"Comment\0"));
+        assert!(chunk.data().split(|&b| b == b'\n').all(|line| line.len() <= 8 + LINE_LENGTH));
+        assert_eq!(extract(&png, &TextOptions::default()).unwrap(), payload());
+        assert_eq!(detect(&png), vec![("Comment".to_string(), 200)]);
+    }
+
+    #[test]
+    fn test_international_text_round_trip() {
+        let mut png = testing_png();
+        let options = TextOptions {
+            keyword: "Description".to_string(),
+            international: true,
+        };
+        embed(&mut png, &payload(), &options).unwrap();
+        assert!(png.chunk_by_type("iTXt").unwrap().data().starts_with(b"Description\0\0\0\0\0"));
+        assert_eq!(extract(&png, &options).unwrap(), payload());
+        assert!(extract(&png, &TextOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_keyword() {
+        let mut png = testing_png();
+        for keyword in ["", " Comment", "Comm\u{e9}nt"] {
+            let options = TextOptions {
+                keyword: keyword.to_string(),
+                international: false,
+            };
+            assert!(embed(&mut png, b"x", &options).is_err());
+        }
+        assert!(detect(&testing_png()).is_empty());
+        assert!(extract(&testing_png(), &TextOptions::default()).is_err());
+    }
+}
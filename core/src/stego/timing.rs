@@ -0,0 +1,201 @@
+//! Hides a payload in the frame delays of an animated PNG. A delay of
+//! 1/10 s can be written as 1/10, 2/20, ... 6553/65530; all of them render
+//! the same, so the multiplier carries bits. fcTL is part of the animation,
+//! so the payload survives tools that strip unknown chunks.
+
+use rand::{Rng, RngCore};
+
+use crate::apng::FrameControl;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::stego::{from_bits, to_bits};
+use crate::{Error, Result};
+
+/// Size of the big-endian payload length stored in front of the payload.
+pub const LENGTH_SIZE: usize = 2;
+/// Size of the checksum stored after the length. Without it the delays of
+/// any APNG read back as an empty payload.
+pub const CHECK_SIZE: usize = 2;
+const HEADER_SIZE: usize = LENGTH_SIZE + CHECK_SIZE;
+
+/// Number of payload bytes the frame delays of `png` can carry.
+pub fn capacity(png: &Png) -> Result<usize> {
+    let bits: usize = controls(png)?.iter().map(|c| slot(c).1).sum();
+    Ok((bits / 8).saturating_sub(HEADER_SIZE))
+}
+
+/// Rewrites every fcTL delay as an equivalent fraction that encodes the
+/// next bits of `payload`.
+pub fn embed(png: &mut Png, payload: &[u8]) -> Result<()> {
+    let controls = controls(png)?;
+    let total: usize = controls.iter().map(|c| slot(c).1).sum();
+    let width = total / 8;
+    if payload.len() + HEADER_SIZE > width {
+        return Err(Error::from(format!(
+            "Payload of {} bytes exceeds the frame timing capacity of {} bytes",
+            payload.len(),
+            width.saturating_sub(HEADER_SIZE)
+        )));
+    }
+
+    // 未使用的部分用随机值填充，否则后面的帧会全部回到最简分数
+    let mut rng = rand::thread_rng();
+    let mut framed = vec![0; width];
+    let length = u16::try_from(payload.len())?.to_be_bytes();
+    framed[..LENGTH_SIZE].copy_from_slice(&length);
+    framed[LENGTH_SIZE..HEADER_SIZE].copy_from_slice(&check(&length, payload));
+    framed[HEADER_SIZE..HEADER_SIZE + payload.len()].copy_from_slice(payload);
+    rng.fill_bytes(&mut framed[HEADER_SIZE + payload.len()..]);
+    let mut bits = to_bits(&framed).chain(std::iter::repeat_with(|| rng.gen::<bool>()));
+
+    let mut rewritten = controls.into_iter().map(|control| {
+        let ((num, den), count) = slot(&control);
+        let multiplier = 1 + bits.by_ref().take(count).fold(0u16, |acc, bit| (acc << 1) | bit as u16);
+        FrameControl {
+            delay_num: num * multiplier,
+            delay_den: den * multiplier,
+            ..control
+        }
+        .to_chunk()
+    });
+    let chunks = png
+        .chunks()
+        .iter()
+        .map(|chunk| {
+            if *chunk.chunk_type() == ChunkType::FCTL {
+                rewritten.next().unwrap()
+            } else {
+                chunk.clone()
+            }
+        })
+        .collect();
+    *png = Png::from_chunks(chunks);
+    Ok(())
+}
+
+/// Reads back a payload written by [`embed`].
+pub fn extract(png: &Png) -> Result<Vec<u8>> {
+    let mut bits = Vec::new();
+    for control in controls(png)? {
+        let ((_, den), count) = slot(&control);
+        let multiplier = denominator(&control) / den - 1;
+        bits.extend((0..count).rev().map(|i| (multiplier >> i) & 1 == 1));
+    }
+
+    let framed = from_bits(bits.iter().copied().take(bits.len() / 8 * 8));
+    if framed.len() < HEADER_SIZE {
+        return Err(Error::from("No frame timing payload found"));
+    }
+    let length = u16::from_be_bytes([framed[0], framed[1]]) as usize;
+    if length + HEADER_SIZE > framed.len() {
+        return Err(Error::from("No frame timing payload found"));
+    }
+    let payload = &framed[HEADER_SIZE..HEADER_SIZE + length];
+    if framed[LENGTH_SIZE..HEADER_SIZE] != check(&framed[..LENGTH_SIZE], payload) {
+        return Err(Error::from("No frame timing payload found"));
+    }
+    Ok(payload.to_vec())
+}
+
+// CRC-32 的低 16 位，覆盖长度和载荷
+fn check(length: &[u8], payload: &[u8]) -> [u8; CHECK_SIZE] {
+    let mut digest = crc32fast::Hasher::new();
+    digest.update(length);
+    digest.update(payload);
+    let crc = digest.finalize().to_be_bytes();
+    [crc[2], crc[3]]
+}
+
+fn controls(png: &Png) -> Result<Vec<FrameControl>> {
+    let animation = png
+        .animation()?
+        .ok_or_else(|| Error::from("Frame timing embedding requires an animated PNG"))?;
+    Ok(animation.frames.into_iter().map(|frame| frame.control).collect())
+}
+
+// 分母为 0 表示 1/100 秒
+fn denominator(control: &FrameControl) -> u16 {
+    if control.delay_den == 0 {
+        100
+    } else {
+        control.delay_den
+    }
+}
+
+// 返回最简分数以及这一帧能携带的位数：倍数 k 满足 k*num 和 k*den 都不超过 u16
+fn slot(control: &FrameControl) -> ((u16, u16), usize) {
+    let (num, den) = (control.delay_num, denominator(control));
+    let divisor = gcd(num, den);
+    let (num, den) = (num / divisor, den / divisor);
+    let multipliers = u16::MAX / num.max(den);
+    ((num, den), multipliers.ilog2() as usize)
+}
+
+fn gcd(mut a: u16, mut b: u16) -> u16 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apng;
+    use crate::idat::Raster;
+    use crate::ihdr::{ColorType, Ihdr};
+
+    fn testing_png(delays: &[(u16, u16)]) -> Png {
+        let ihdr = Ihdr {
+            width: 2,
+            height: 2,
+            bit_depth: 8,
+            color_type: ColorType::Grayscale,
+            interlaced: false,
+        };
+        let images: Vec<Png> = (0..delays.len() as u8)
+            .map(|i| Raster::new(ihdr, vec![i; 4]).unwrap().to_png().unwrap())
+            .collect();
+        apng::assemble(&images, delays, 0).unwrap()
+    }
+
+    fn delays(png: &Png) -> Vec<f64> {
+        controls(png).unwrap().iter().map(|c| c.delay()).collect()
+    }
+
+    #[test]
+    fn test_timing_round_trip() {
+        let mut png = testing_png(&[(100, 1000), (1, 20), (0, 0), (3, 7), (1, 10), (0, 0)]);
+        let before = delays(&png);
+        let frames = apng::render(&png).unwrap();
+        // 1/10 和 0 分别能携带 12 位和 15 位
+        assert_eq!(capacity(&png).unwrap(), 5);
+
+        embed(&mut png, b"tick!").unwrap();
+        assert_eq!(extract(&png).unwrap(), b"tick!");
+        assert_eq!(delays(&png), before);
+        assert_eq!(apng::render(&png).unwrap(), frames);
+        assert!(embed(&mut png, b"too long").is_err());
+    }
+
+    #[test]
+    fn test_timing_without_payload() {
+        // 最简分数的延时全部读出 0，校验和不匹配
+        let png = testing_png(&[(1, 10), (1, 20), (0, 0)]);
+        assert!(extract(&png).is_err());
+    }
+
+    #[test]
+    fn test_timing_requires_animation() {
+        let ihdr = Ihdr {
+            width: 1,
+            height: 1,
+            bit_depth: 8,
+            color_type: ColorType::Grayscale,
+            interlaced: false,
+        };
+        let mut still = Raster::new(ihdr, vec![0]).unwrap().to_png().unwrap();
+        assert!(capacity(&still).is_err());
+        assert!(embed(&mut still, b"x").is_err());
+    }
+}
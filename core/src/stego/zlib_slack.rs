@@ -0,0 +1,139 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::idat::zlib_stream_len;
+use crate::png::Png;
+use crate::{Error, Result};
+
+/// Stores `payload` after the end of the zlib stream in the last IDAT chunk.
+/// Decoders stop at the end of the stream, so the image renders unchanged
+/// and the chunk layout stays the same. Existing slack is replaced.
+///
+/// IDAT is still a changed critical chunk, so unknown unsafe-to-copy chunks
+/// are removed and returned.
+pub fn embed(png: &mut Png, payload: &[u8]) -> Result<Vec<Chunk>> {
+    let (stream, idats) = split(png)?;
+    let mut data = stream;
+    data.extend_from_slice(payload);
+
+    // 保持原来的 IDAT 切分方式，多出来的数据全部放进最后一个 IDAT
+    let mut chunks = Vec::with_capacity(idats.len());
+    let mut rest = &data[..];
+    for (i, idat) in idats.iter().enumerate() {
+        let take = if i + 1 == idats.len() {
+            rest.len()
+        } else {
+            (idat.length() as usize).min(rest.len())
+        };
+        let (head, tail) = rest.split_at(take);
+        chunks.push(Chunk::new(idat.chunk_type().clone(), head.to_vec()));
+        rest = tail;
+        if rest.is_empty() {
+            break;
+        }
+    }
+    png.replace_idat_chunks(chunks);
+    Ok(png.remove_unsafe_to_copy())
+}
+
+/// Returns the bytes following the zlib stream, or an error if there are
+/// none.
+pub fn extract(png: &Png) -> Result<Vec<u8>> {
+    let data = png.image_data();
+    let end = zlib_stream_len(&data)?;
+    if end == data.len() {
+        return Err(Error::from("No data after the zlib stream"));
+    }
+    Ok(data[end..].to_vec())
+}
+
+/// Number of bytes hidden after the zlib stream, if any.
+pub fn detect(png: &Png) -> Result<Option<usize>> {
+    let data = png.image_data();
+    let end = zlib_stream_len(&data)?;
+    Ok((end < data.len()).then_some(data.len() - end))
+}
+
+// 返回去掉尾部数据后的 zlib 流以及原有的 IDAT 块
+fn split(png: &Png) -> Result<(Vec<u8>, Vec<&Chunk>)> {
+    let idats: Vec<&Chunk> = png
+        .chunks()
+        .iter()
+        .filter(|c| *c.chunk_type() == ChunkType::IDAT)
+        .collect();
+    if idats.is_empty() {
+        return Err(Error::from("PNG has no IDAT chunk"));
+    }
+    let mut data = png.image_data();
+    data.truncate(zlib_stream_len(&data)?);
+    Ok((data, idats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::idat::{deflate, Raster};
+    use crate::ihdr::{ColorType, Ihdr};
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        let ihdr = Ihdr {
+            width: 32,
+            height: 32,
+            bit_depth: 8,
+            color_type: ColorType::Grayscale,
+            interlaced: false,
+        };
+        let filtered: Vec<u8> = (0..32 * 33).map(|i| if i % 33 == 0 { 0 } else { (i * 5) as u8 }).collect();
+        let stream = deflate(&filtered, 6).unwrap();
+        let (first, second) = stream.split_at(stream.len() / 2);
+        Png::from_chunks(vec![
+            ihdr.to_chunk(),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), first.to_vec()),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), second.to_vec()),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ])
+    }
+
+    fn idat_count(png: &Png) -> usize {
+        png.chunks().iter().filter(|c| c.chunk_type().bytes() == *b"IDAT").count()
+    }
+
+    #[test]
+    fn test_zlib_slack_round_trip() {
+        let mut png = testing_png();
+        let raster = Raster::decode(&png).unwrap();
+        assert_eq!(detect(&png).unwrap(), None);
+
+        embed(&mut png, b"after the end").unwrap();
+        assert_eq!(extract(&png).unwrap(), b"after the end");
+        assert_eq!(detect(&png).unwrap(), Some(13));
+        assert_eq!(idat_count(&png), 2);
+        assert_eq!(Raster::decode(&png).unwrap(), raster);
+    }
+
+    #[test]
+    fn test_zlib_slack_replaces_existing() {
+        let mut png = testing_png();
+        embed(&mut png, b"first, much longer payload").unwrap();
+        embed(&mut png, b"second").unwrap();
+        assert_eq!(extract(&png).unwrap(), b"second");
+        assert_eq!(idat_count(&png), 2);
+    }
+
+    #[test]
+    fn test_zlib_slack_drops_unsafe_to_copy_chunks() {
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruST").unwrap(), Vec::new()));
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), Vec::new()));
+        let dropped = embed(&mut png, b"slack").unwrap();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].chunk_type().to_string(), "ruST");
+        assert!(png.chunk_by_type("ruST").is_none());
+        assert!(png.chunk_by_type("ruSt").is_some());
+    }
+
+    #[test]
+    fn test_zlib_slack_missing() {
+        assert!(extract(&testing_png()).is_err());
+    }
+}
@@ -14,6 +14,7 @@ struct Application {
     file_path: String,
     chunk_type: String,
     chunk_data: String,
+    password: String,
     output_msg: String,
 }
 
@@ -24,8 +25,10 @@ enum Message {
     DecodeButtonPressed,
     RemoveButtonPressed,
     PrintButtonPressed,
+    ValidateButtonPressed,
     ChunkTypeInputChanged(String),
     ChunkDataInputChanged(String),
+    PasswordInputChanged(String),
 }
 
 impl Application {
@@ -45,8 +48,11 @@ impl Application {
             Message::ChunkDataInputChanged(text) => {
                 self.chunk_data = text;
             }
+            Message::PasswordInputChanged(text) => {
+                self.password = text;
+            }
             Message::EncodeButtonPressed => {
-                match png_tools::encode(&self.file_path, &self.chunk_type, &self.chunk_data) {
+                match png_tools::encode(&self.file_path, &self.chunk_type, &self.chunk_data, &self.password) {
                     Ok(path) => {
                         self.output_msg = format!("Chunk encoded to {}", path);
                     }
@@ -56,7 +62,7 @@ impl Application {
                 }
             }
             Message::DecodeButtonPressed => {
-                match png_tools::decode(&self.file_path, &self.chunk_type) {
+                match png_tools::decode(&self.file_path, &self.chunk_type, &self.password) {
                     Ok(data) => {
                         self.output_msg = format!("Chunk data: {}", data);
                     }
@@ -83,10 +89,18 @@ impl Application {
                     self.output_msg = format!("Error: {}", e);
                 }
             },
+            Message::ValidateButtonPressed => match png_tools::validate(&self.file_path) {
+                Ok(report) => {
+                    self.output_msg = format!("Validation: {}", report);
+                }
+                Err(e) => {
+                    self.output_msg = format!("Error: {}", e);
+                }
+            },
         }
     }
 
-    fn view(&self) -> Element<Message> {
+    fn view(&self) -> Element<'_, Message> {
         let able = !(self.file_path.is_empty() || self.chunk_type.is_empty());
 
         let encode_able = !self.file_path.is_empty()
@@ -103,13 +117,19 @@ impl Application {
             text_input("CHUNK TYPE 4 BYTE", &self.chunk_type)
                 .on_input(Message::ChunkTypeInputChanged),
             text_input("CHUNK DATA", &self.chunk_data).on_input(Message::ChunkDataInputChanged),
+            text_input("PASSWORD (OPTIONAL)", &self.password)
+                .secure(true)
+                .on_input(Message::PasswordInputChanged),
             horizontal_space(),
             button(text("ENCODE"))
-                .on_press_maybe(encode_able.then(|| Message::EncodeButtonPressed)),
-            button(text("DECODE")).on_press_maybe(able.then(|| (Message::DecodeButtonPressed))),
-            button(text("REMOVE")).on_press_maybe(able.then(|| (Message::RemoveButtonPressed))),
+                .on_press_maybe(encode_able.then_some(Message::EncodeButtonPressed)),
+            button(text("DECODE")).on_press_maybe(able.then_some(Message::DecodeButtonPressed)),
+            button(text("REMOVE")).on_press_maybe(able.then_some(Message::RemoveButtonPressed)),
             button(text("PRINT")).on_press_maybe(
-                (!self.file_path.is_empty()).then(|| (Message::PrintButtonPressed))
+                (!self.file_path.is_empty()).then_some(Message::PrintButtonPressed)
+            ),
+            button(text("VALIDATE")).on_press_maybe(
+                (!self.file_path.is_empty()).then_some(Message::ValidateButtonPressed)
             ),
         ]
         .spacing(10);
@@ -1,4 +1,4 @@
-use core::{chunk::Chunk, chunk_type::ChunkType, png::Png, Result};
+use core::{chunk::Chunk, chunk_type::ChunkType, fragment, registry::{self, KnownChunkType}, envelope::{Compression, Decryption, Encryption, Message}, png::Png, Result};
 use std::{
     fs::File,
     io::{Read, Write},
@@ -6,13 +6,20 @@ use std::{
     str::FromStr,
 };
 
-pub fn encode(png_path: &str, chunk_type: &str, chunk_data: &str) -> Result<String> {
+// password 为空时不加密
+pub fn encode(png_path: &str, chunk_type: &str, chunk_data: &str, password: &str) -> Result<String> {
     let mut png = open_png(png_path)?;
     let c_type = ChunkType::from_str(chunk_type)?;
-    let chunk = Chunk::new(c_type, chunk_data.as_bytes().to_vec());
+    // 不要把消息写进规范定义的块里
+    if let Some(known) = KnownChunkType::from_chunk_type(&c_type) {
+        return Err(format!("{} is a standard chunk ({}), use a private type such as ruSt", chunk_type, known.description()).into());
+    }
+    let encryption = (!password.is_empty()).then(|| Encryption::Passphrase(password.to_string()));
+    let data = Message::text(chunk_data).seal(Compression::None, encryption.as_ref())?;
+    let chunk = Chunk::new(c_type, data);
     png.append_chunk(chunk);
 
-    println!("{}", png.to_string());
+    println!("{}", png);
     
     let path = Path::new(png_path);
     let mut path_buf = path
@@ -24,12 +31,18 @@ pub fn encode(png_path: &str, chunk_type: &str, chunk_data: &str) -> Result<Stri
     new_file.write_all(&png.as_bytes())?;
     Ok(path_buf.display().to_string())
 }
-pub fn decode(png_path: &str, chunk_type: &str) -> Result<String> {
+pub fn decode(png_path: &str, chunk_type: &str, password: &str) -> Result<String> {
     let png = open_png(png_path)?;
     print!("chunk_type : {}",chunk_type);
-    let chunk = png.chunk_by_type(chunk_type).ok_or("Chunk Type not found.")?;
-    let chunk_data_str = chunk.data_as_string()?;
-    Ok(chunk_data_str)
+    png.chunk_by_type(chunk_type).ok_or("Chunk Type not found.")?;
+    let data = fragment::extract(&png, chunk_type)?;
+    let decryption = (!password.is_empty()).then(|| Decryption::Passphrase(password.to_string()));
+    let message = Message::open(&data, decryption.as_ref())?;
+    // 和命令行一样拒绝读取过期的消息
+    if message.metadata.is_expired() {
+        return Err("The message has expired".into());
+    }
+    Ok(String::from_utf8(message.data)?)
 }
 
 pub fn remove(png_path: &str, chunk_type: &str) -> Result<String> {
@@ -45,10 +58,19 @@ pub fn print(png_path: &str)-> Result<String> {
     Ok(png.to_string())
 }
 
+pub fn validate(png_path: &str) -> Result<String> {
+    let png = open_png(png_path)?;
+    let issues: Vec<String> = registry::validate(&png).iter().map(|i| i.to_string()).collect();
+    if issues.is_empty() {
+        return Ok("follows the specification".to_string());
+    }
+    Ok(issues.join("\n"))
+}
+
 fn open_png(path: &str) -> Result<Png> {
     println!("Opening PNG file: {}", path);
     let mut file = File::open(path)?;
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer)?;
-    Png::try_from(buffer.as_slice()).map_err(|e| e.into())
+    Png::try_from(buffer.as_slice())
 }